@@ -1,127 +1,133 @@
+mod autotile;
+mod error_overlay;
+mod objectives;
+mod player;
 mod render_bg;
+mod stats;
+mod textures;
 
-use std::{
-    cell::{Cell, RefCell},
-    rc::Rc,
-};
+use eframe::egui::{self, Frame};
 
-use eframe::{
-    egui::{self, Frame},
-    epaint::{pos2, Pos2},
+pub(crate) use self::textures::Sprite;
+use self::{
+    autotile::{parse_tilesets, Tileset},
+    error_overlay::{ErrorOverlay, ErrorSource},
+    stats::ScoreRule,
+    textures::Textures,
 };
-
-use rand::Rng;
-use ruscal::{parse_args, Args};
-
 use crate::{
-    bg_image::BgImage,
-    raccoon::{compile_program, Raccoon},
+    assets::Assets,
+    cli::Options,
+    raccoon::{Action, AgentKind},
+    scenario::{Progress, Scenario, Status},
+    world::World,
 };
 
-pub(crate) const CELL_SIZE: usize = 64;
-pub(crate) const CELL_SIZE_F: f32 = CELL_SIZE as f32;
-pub(crate) const BOARD_SIZE: usize = 12;
-pub(crate) const BOARD_SIZE_I: i32 = BOARD_SIZE as i32;
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub(crate) enum MapCell {
-    Wall,
-    Empty(u8),
-}
-
-impl MapCell {
-    fn is_wall(&self) -> bool {
-        matches!(self, Self::Wall)
-    }
-}
-
-pub(crate) struct Hole {
-    pub pos: Pos2,
-    pub occupied: Cell<bool>,
-}
+pub(crate) const STATS_WIDTH: f32 = 250.;
+/// Time between simulation ticks.
+pub(crate) const TICK_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
 
 pub(crate) struct RuccoonApp {
-    bg: BgImage,
-    weeds_img: Option<egui::TextureHandle>,
-    wall_img: Option<egui::TextureHandle>,
-    map: Rc<Vec<MapCell>>,
-    raccoon_img: Option<egui::TextureHandle>,
-    raccoons: Vec<Raccoon>,
-    corn_img: Option<egui::TextureHandle>,
-    items: Rc<RefCell<Vec<Pos2>>>,
-    hole_img: Option<egui::TextureHandle>,
-    holes: Rc<Vec<Hole>>,
+    assets: Assets,
+    errors: ErrorOverlay,
+    textures: Textures,
+    /// Terrains that are drawn with autotiling.
+    tilesets: Vec<Tileset>,
+    world: World,
+    scenario: Option<Scenario>,
+    /// How the world is doing with the objectives of the scenario.
+    progress: Option<Progress>,
+    score_rule: ScoreRule,
     last_animate: Option<std::time::Instant>,
     paused: bool,
 }
 
 impl RuccoonApp {
-    pub fn new() -> Self {
-        let args = parse_args(true).unwrap_or_else(|| {
-            let mut args = Args::new();
-            args.source = Some("scripts/raccoon.rscl".to_string());
-            args
+    pub fn new(options: &Options) -> Self {
+        let mut errors = ErrorOverlay::default();
+        let assets = Assets::new(options.assets.as_deref()).unwrap_or_else(|e| {
+            errors.report(ErrorSource::Setup, e);
+            Assets::embedded()
         });
 
-        let mut map = vec![MapCell::Empty(0); BOARD_SIZE * BOARD_SIZE];
-        let mut rng = rand::thread_rng();
-        for i in 0..BOARD_SIZE {
-            for j in 0..BOARD_SIZE {
-                map[i + BOARD_SIZE * j] = if rng.gen::<f32>() < 0.25 {
-                    MapCell::Wall
-                } else {
-                    MapCell::Empty(rng.gen_range(0..7))
-                };
-            }
+        let scenario = options.load_scenario().unwrap_or_else(|e| {
+            errors.report(ErrorSource::Setup, e);
+            None
+        });
+        let mut world_errors = vec![];
+        let world =
+            World::with_scenario(options, &[], scenario.as_ref(), &assets, &mut world_errors);
+        if options.play {
+            let first = world
+                .raccoons
+                .iter()
+                .find(|raccoon| raccoon.kind == AgentKind::Raccoon);
+            world.shared.player.set(first.map(|raccoon| raccoon.id));
+        }
+        for e in world_errors {
+            errors.report(ErrorSource::Setup, e);
         }
-        let holes = Rc::new(
-            (0..2)
-                .map(|_| Hole {
-                    pos: generate_pos(|pos| is_blocked(pos, &map, &[])),
-                    occupied: Cell::new(false),
-                })
-                .collect(),
-        );
-        let map = Rc::new(map);
 
-        let bytecode = match compile_program(&args) {
-            Ok(bytecode) => bytecode,
-            Err(e) => panic!("Compile error: {e}"),
-        };
-        let program = Rc::new(bytecode);
-        let items = Rc::new(RefCell::new(vec![]));
+        let tilesets = load_tilesets(&assets, &mut errors);
+
         Self {
-            bg: BgImage::new(),
-            weeds_img: None,
-            wall_img: None,
-            map: map.clone(),
-            raccoon_img: None,
-            raccoons: (0..2)
-                .map(|i| Raccoon::new(i, &map, &items, &holes, &program, args.debug_output))
-                .collect(),
-            corn_img: None,
-            items,
-            hole_img: None,
-            holes,
+            assets,
+            errors,
+            textures: Textures::new(options.atlas),
+            tilesets,
+            world,
+            progress: scenario.as_ref().map(Progress::new),
+            scenario,
+            score_rule: ScoreRule::default(),
             last_animate: None,
             paused: false,
         }
     }
 
-    fn animate(&mut self) {
-        if !self.paused {
-            for raccoon in &self.raccoons {
-                raccoon.animate(&self.raccoons, &self.map, &self.items, &self.holes);
+    /// Load images and tilesets from the assets again, to see changes without restarting.
+    /// Scripts are not reloaded, since the raccoons are running them.
+    fn reload_assets(&mut self) {
+        self.errors.clear(ErrorSource::Assets);
+        self.textures.reload();
+        self.tilesets = load_tilesets(&self.assets, &mut self.errors);
+    }
+
+    /// Remember what the player asks the player's raccoon to do, until the next tick.
+    fn read_player_input(&self, ctx: &egui::Context) {
+        let shared = &self.world.shared;
+        let Some(player) = shared.player.get() else {
+            return;
+        };
+        let Some(raccoon) = self
+            .world
+            .raccoons
+            .iter()
+            .find(|raccoon| raccoon.id == player)
+        else {
+            return;
+        };
+        let in_hole = raccoon.state.borrow().in_hole;
+        match player::player_action(ctx, shared.movement.get(), in_hole) {
+            Some(action) => shared.player_action.set(Some(action)),
+            // A move lasts while its keys are held, so releasing them before the tick cancels it.
+            // Key presses wait for the tick, since the frame after them has no key down.
+            None if matches!(shared.player_action.get(), Some(Action::Move(_))) => {
+                shared.player_action.set(None)
             }
-            // self.paused = true;
+            None => {}
         }
+    }
 
-        let mut rng = rand::thread_rng();
-        if self.items.borrow().len() < 10 && rng.gen::<f64>() < 0.1 {
-            let pos = generate_pos(|pos| is_blocked(pos, &self.map, &self.items.borrow()));
-            let mut items = self.items.borrow_mut();
-            if items.iter().all(|item| *item != pos) {
-                items.push(pos);
+    fn animate(&mut self) {
+        if self.paused {
+            return;
+        }
+        self.world.tick();
+        // The world stops once the scenario is decided, to show how it ended.
+        if let (Some(scenario), Some(progress)) = (&self.scenario, &mut self.progress) {
+            progress.update(scenario, &self.world);
+            if progress.outcome() != Status::Pending {
+                self.paused = true;
             }
         }
     }
@@ -129,50 +135,46 @@ impl RuccoonApp {
 
 impl eframe::App for RuccoonApp {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
-        ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        // Repaint more often than we tick, so that agents slide smoothly between cells.
+        ctx.request_repaint_after(TICK_INTERVAL / 4);
+        self.read_player_input(ctx);
         let now = std::time::Instant::now();
         if !self
             .last_animate
-            .is_some_and(|time| !(std::time::Duration::from_millis(100) < now - time))
+            .is_some_and(|time| !(TICK_INTERVAL < now - time))
         {
             self.animate();
             self.last_animate = Some(now);
         }
+        eframe::egui::SidePanel::right("stats")
+            .min_width(STATS_WIDTH)
+            .show(ctx, |ui| {
+                if ui.button("Reload assets").clicked() {
+                    self.reload_assets();
+                }
+                self.show_stats(ui);
+            });
         eframe::egui::CentralPanel::default().show(ctx, |ui| {
             Frame::canvas(ui.style()).show(ui, |ui| {
                 let (response, painter) =
                     ui.allocate_painter(ui.available_size(), eframe::egui::Sense::hover());
-                let res = self.render_bg(&response, &painter);
-                if let Err(res) = res {
-                    eprintln!("image rendering error: {res}");
-                }
+                self.render_bg(&response, &painter);
+                self.render_outcome(&painter, response.rect);
+                self.errors.render(&painter, response.rect);
             });
         });
     }
 }
 
-fn is_blocked(pos: Pos2, map: &[MapCell], items: &[Pos2]) -> bool {
-    if !matches!(
-        map[pos.x as usize + pos.y as usize * BOARD_SIZE],
-        MapCell::Empty(_)
-    ) {
-        return true;
-    }
-    if items.iter().any(|item| *item == pos) {
-        return true;
-    }
-    false
-}
-
-fn generate_pos(blocked: impl Fn(Pos2) -> bool) -> Pos2 {
-    let mut rng = rand::thread_rng();
-    loop {
-        let pos = pos2(
-            rng.gen_range(0..BOARD_SIZE) as f32,
-            rng.gen_range(0..BOARD_SIZE) as f32,
-        );
-        if !blocked(pos) {
-            return pos;
-        }
-    }
+fn load_tilesets(assets: &Assets, errors: &mut ErrorOverlay) -> Vec<Tileset> {
+    assets
+        .path("tilesets")
+        .and_then(|path| {
+            let src = assets.read_to_string(path)?;
+            parse_tilesets(&src).map_err(|e| format!("{path}:{e}"))
+        })
+        .unwrap_or_else(|e| {
+            errors.report(ErrorSource::Assets, format!("Failed to load tilesets: {e}"));
+            vec![]
+        })
 }
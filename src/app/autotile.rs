@@ -0,0 +1,249 @@
+//! Terrain drawn from tilesets that join up with their neighbours, as described in
+//! `assets/tilesets.ini`.
+
+use eframe::{
+    egui::Painter,
+    emath::RectTransform,
+    epaint::{pos2, Color32, Rect, Vec2},
+};
+
+use super::textures::Sprite;
+use crate::{
+    ini,
+    world::{MapCell, BOARD_SIZE, CELL_SIZE_F},
+};
+
+/// The tile a quarter of a cell uses, depending on which of its neighbours connect to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Shape {
+    Full,
+    Inner,
+    Vertical,
+    Horizontal,
+    Outer,
+}
+
+impl Shape {
+    const ALL: [Self; 5] = [
+        Self::Full,
+        Self::Inner,
+        Self::Vertical,
+        Self::Horizontal,
+        Self::Outer,
+    ];
+
+    fn key(&self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Inner => "inner",
+            Self::Vertical => "vertical",
+            Self::Horizontal => "horizontal",
+            Self::Outer => "outer",
+        }
+    }
+
+    /// The shape of a quarter given whether its vertical side, horizontal side and corner
+    /// neighbours connect. The corner only matters if both sides connect.
+    pub(crate) fn of_quarter(vertical: bool, horizontal: bool, corner: bool) -> Self {
+        match (vertical, horizontal, corner) {
+            (true, true, true) => Self::Full,
+            (true, true, false) => Self::Inner,
+            (true, false, _) => Self::Vertical,
+            (false, true, _) => Self::Horizontal,
+            (false, false, _) => Self::Outer,
+        }
+    }
+}
+
+/// Directions of the quarters of a cell, in the order top left, top right, bottom left and
+/// bottom right.
+const QUARTERS: [[isize; 2]; 4] = [[-1, -1], [1, -1], [-1, 1], [1, 1]];
+
+/// The shapes of the four quarters of a cell. `connects(dx, dy)` tells whether the neighbour at
+/// that offset connects to the cell.
+pub(crate) fn cell_shapes(connects: impl Fn(isize, isize) -> bool) -> [Shape; 4] {
+    QUARTERS.map(|[dx, dy]| Shape::of_quarter(connects(0, dy), connects(dx, 0), connects(dx, dy)))
+}
+
+pub(crate) struct Tileset {
+    /// The terrain drawn with this tileset.
+    pub terrain: String,
+    /// Asset id of the image.
+    pub image: String,
+    /// Terrains that join up with this one, including itself.
+    connects: Vec<String>,
+    tile_size: f32,
+    /// Pixel positions of the tiles, indexed by shape and then by quarter.
+    tiles: [[[f32; 2]; 4]; 5],
+}
+
+impl Tileset {
+    /// Draw the cell at (`x`, `y`), picking tiles from its neighbours in `map`. `sprite` is the
+    /// image named by `image`.
+    pub(crate) fn draw(
+        &self,
+        x: usize,
+        y: usize,
+        map: &[MapCell],
+        painter: &Painter,
+        to_screen: &RectTransform,
+        sprite: &Sprite,
+    ) {
+        let terrain = |dx: isize, dy: isize| {
+            let x = (x as isize + dx).max(0).min(BOARD_SIZE as isize - 1) as usize;
+            let y = (y as isize + dy).max(0).min(BOARD_SIZE as isize - 1) as usize;
+            map[x + y * BOARD_SIZE]
+        };
+        let shapes = cell_shapes(|dx, dy| {
+            let name = terrain(dx, dy).name();
+            self.connects.iter().any(|terrain| terrain == name)
+        });
+
+        let image_size = sprite.size;
+        for (quarter, (shape, [dx, dy])) in shapes.iter().zip(QUARTERS).enumerate() {
+            let [u, v] = self.tiles[*shape as usize][quarter];
+            let tex_rect = Rect::from_min_max(
+                pos2(u / image_size.x, v / image_size.y),
+                pos2(
+                    (u + self.tile_size) / image_size.x,
+                    (v + self.tile_size) / image_size.y,
+                ),
+            );
+            let (xofs, yofs) = ((dx + 1) as f32 * 0.25, (dy + 1) as f32 * 0.25);
+            sprite.paint(
+                painter,
+                to_screen.transform_rect(Rect::from_min_size(
+                    pos2(
+                        (x as f32 + xofs) * CELL_SIZE_F,
+                        (y as f32 + yofs) * CELL_SIZE_F,
+                    ),
+                    Vec2::splat(CELL_SIZE_F * 0.5),
+                )),
+                tex_rect,
+                Color32::WHITE,
+            );
+        }
+    }
+}
+
+pub(crate) fn parse_tilesets(src: &str) -> Result<Vec<Tileset>, String> {
+    ini::parse(src)?
+        .iter()
+        .map(|section| {
+            if !MapCell::NAMES.contains(&section.name.as_str()) {
+                return Err(format!(
+                    "{}: unknown terrain `{}`, expected one of {}",
+                    section.line,
+                    section.name,
+                    MapCell::NAMES.join(", ")
+                ));
+            }
+            let mut connects = vec![section.name.clone()];
+            if let Some(entry) = section.get("connects") {
+                connects.extend(entry.value.split_whitespace().map(|s| s.to_string()));
+            }
+            let mut tiles = [[[0.; 2]; 4]; 5];
+            for shape in Shape::ALL {
+                let entry = section.require(shape.key())?;
+                let quarters: Vec<_> = entry.value.split(',').collect();
+                if quarters.len() != QUARTERS.len() {
+                    return Err(format!(
+                        "{}: `{}` needs a tile for each of the 4 quarters",
+                        entry.line, entry.key
+                    ));
+                }
+                for (quarter, tile) in quarters.iter().enumerate() {
+                    let coords = tile
+                        .split_whitespace()
+                        .map(|s| s.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| format!("{}: bad tile position: {e}", entry.line))?;
+                    let [x, y] = coords[..] else {
+                        return Err(format!(
+                            "{}: tile position `{}` should be `x y`",
+                            entry.line,
+                            tile.trim()
+                        ));
+                    };
+                    tiles[shape as usize][quarter] = [x, y];
+                }
+            }
+            Ok(Tileset {
+                terrain: section.name.clone(),
+                image: section.require("image")?.value.clone(),
+                connects,
+                tile_size: section.require("tile_size")?.parse()?,
+                tiles,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_shapes() {
+        assert_eq!(Shape::of_quarter(true, true, true), Shape::Full);
+        assert_eq!(Shape::of_quarter(true, true, false), Shape::Inner);
+        assert_eq!(Shape::of_quarter(true, false, true), Shape::Vertical);
+        assert_eq!(Shape::of_quarter(true, false, false), Shape::Vertical);
+        assert_eq!(Shape::of_quarter(false, true, true), Shape::Horizontal);
+        assert_eq!(Shape::of_quarter(false, true, false), Shape::Horizontal);
+        assert_eq!(Shape::of_quarter(false, false, true), Shape::Outer);
+        assert_eq!(Shape::of_quarter(false, false, false), Shape::Outer);
+    }
+
+    #[test]
+    fn isolated_and_surrounded_cells() {
+        assert_eq!(cell_shapes(|_, _| false), [Shape::Outer; 4]);
+        assert_eq!(cell_shapes(|_, _| true), [Shape::Full; 4]);
+    }
+
+    #[test]
+    fn each_quarter_looks_at_its_own_neighbours() {
+        // Only the cell above connects.
+        assert_eq!(
+            cell_shapes(|dx, dy| dx == 0 && dy == -1),
+            [Shape::Vertical, Shape::Vertical, Shape::Outer, Shape::Outer]
+        );
+        // Only the cell to the right connects.
+        assert_eq!(
+            cell_shapes(|dx, dy| dx == 1 && dy == 0),
+            [
+                Shape::Outer,
+                Shape::Horizontal,
+                Shape::Outer,
+                Shape::Horizontal
+            ]
+        );
+        // Everything but the bottom left corner connects.
+        assert_eq!(
+            cell_shapes(|dx, dy| !(dx == -1 && dy == 1)),
+            [Shape::Full, Shape::Full, Shape::Inner, Shape::Full]
+        );
+    }
+
+    #[test]
+    fn shipped_wall_tileset_matches_the_original_layout() {
+        let tilesets = parse_tilesets(include_str!("../../assets/tilesets.ini")).unwrap();
+        let wall = tilesets.iter().find(|t| t.terrain == "wall").unwrap();
+        assert_eq!(wall.tile_size, 16.);
+        assert_eq!(wall.tiles[Shape::Full as usize][0], [0., 32.]);
+        assert_eq!(wall.tiles[Shape::Inner as usize][3], [32., 32.]);
+        assert_eq!(wall.tiles[Shape::Vertical as usize][1], [48., 80.]);
+        assert_eq!(wall.tiles[Shape::Horizontal as usize][2], [32., 80.]);
+        assert_eq!(wall.tiles[Shape::Outer as usize][1], [48., 0.]);
+        assert_eq!(wall.connects, ["wall"]);
+    }
+
+    #[test]
+    fn bad_tilesets() {
+        assert!(parse_tilesets("[lava]\nimage = x.png").is_err());
+        let missing_shape = "[wall]\nimage = x.png\ntile_size = 16\nfull = 0 0, 0 0, 0 0, 0 0";
+        assert!(parse_tilesets(missing_shape).is_err());
+        let short = "[wall]\nimage = x.png\ntile_size = 16\nfull = 0 0, 0 0";
+        assert!(parse_tilesets(short).is_err());
+    }
+}
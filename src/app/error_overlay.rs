@@ -0,0 +1,70 @@
+//! Problems that keep the game from working properly, such as missing assets or scripts that do
+//! not compile, shown on top of the board rather than only on the console.
+
+use eframe::{
+    egui::Painter,
+    emath::Align2,
+    epaint::{pos2, vec2, Color32, FontId, Rect},
+};
+
+/// What a problem came from, so that reloading one kind of thing only forgets its own problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorSource {
+    /// Images and tilesets, which can be reloaded.
+    Assets,
+    /// The asset directory, the scenario, the scripts and the director, which are only loaded
+    /// when the game starts.
+    Setup,
+}
+
+#[derive(Default)]
+pub(crate) struct ErrorOverlay {
+    messages: Vec<(ErrorSource, String)>,
+}
+
+impl ErrorOverlay {
+    /// Report a problem. The same message is only reported once, since failed loads are retried
+    /// every frame.
+    pub(crate) fn report(&mut self, source: ErrorSource, message: impl Into<String>) {
+        let message = message.into();
+        if !self.messages.iter().any(|(_, known)| *known == message) {
+            eprintln!("{message}");
+            self.messages.push((source, message));
+        }
+    }
+
+    /// Forget the problems that came from `source`, before loading it again.
+    pub(crate) fn clear(&mut self, source: ErrorSource) {
+        self.messages.retain(|(known, _)| *known != source);
+    }
+
+    pub(crate) fn render(&self, painter: &Painter, rect: Rect) {
+        if self.messages.is_empty() {
+            return;
+        }
+        const LINE_HEIGHT: f32 = 18.;
+        const MARGIN: f32 = 8.;
+        let font = FontId::monospace(14.);
+        let height = (self.messages.len() + 1) as f32 * LINE_HEIGHT + 2. * MARGIN;
+        let bg = Rect::from_min_size(rect.min, vec2(rect.width(), height));
+        painter.rect_filled(bg, 0., Color32::from_black_alpha(200));
+        let mut pos = pos2(rect.min.x + MARGIN, rect.min.y + MARGIN);
+        painter.text(
+            pos,
+            Align2::LEFT_TOP,
+            "Errors:",
+            font.clone(),
+            Color32::WHITE,
+        );
+        for (_, message) in &self.messages {
+            pos.y += LINE_HEIGHT;
+            painter.text(
+                pos,
+                Align2::LEFT_TOP,
+                message,
+                font.clone(),
+                Color32::from_rgb(255, 96, 96),
+            );
+        }
+    }
+}
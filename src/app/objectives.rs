@@ -0,0 +1,76 @@
+//! The objectives of the scenario being played, and how it ended.
+
+use eframe::{
+    egui::{self, Painter, RichText},
+    emath::Align2,
+    epaint::{vec2, Color32, FontId, Rect},
+};
+
+use super::RuccoonApp;
+use crate::scenario::Status;
+
+fn status_color(status: Status) -> Color32 {
+    match status {
+        Status::Pending => Color32::GRAY,
+        Status::Succeeded => Color32::from_rgb(96, 224, 96),
+        Status::Failed => Color32::from_rgb(255, 96, 96),
+    }
+}
+
+impl RuccoonApp {
+    pub(super) fn show_objectives(&self, ui: &mut egui::Ui) {
+        let (Some(scenario), Some(progress)) = (&self.scenario, &self.progress) else {
+            return;
+        };
+        ui.heading(&scenario.name);
+        if !scenario.description.is_empty() {
+            ui.label(&scenario.description);
+        }
+        let left = scenario.time_limit.saturating_sub(self.world.ticks());
+        ui.label(format!("Time left: {left} ticks"));
+        for (objective, status) in scenario.objectives.iter().zip(&progress.statuses) {
+            ui.label(
+                RichText::new(format!("{}: {}", objective.describe(), status.name()))
+                    .color(status_color(*status)),
+            );
+        }
+    }
+
+    /// Announce the end of the scenario over the board, once it is decided.
+    pub(super) fn render_outcome(&self, painter: &Painter, rect: Rect) {
+        let (Some(scenario), Some(progress)) = (&self.scenario, &self.progress) else {
+            return;
+        };
+        let outcome = progress.outcome();
+        let title = match outcome {
+            Status::Pending => return,
+            Status::Succeeded => "Scenario complete",
+            Status::Failed => "Scenario failed",
+        };
+        let stats = self.world.team_stats();
+        let summary = format!(
+            "{} ticks, {} raccoons alive, {} corn eaten, {} delivered",
+            self.world.ticks(),
+            self.world.population(),
+            stats.iter().map(|team| team.eaten).sum::<usize>(),
+            stats.iter().map(|team| team.delivered).sum::<usize>()
+        );
+
+        let bg = Rect::from_center_size(rect.center(), vec2(rect.width(), 100.));
+        painter.rect_filled(bg, 0., Color32::from_black_alpha(200));
+        painter.text(
+            bg.center() - vec2(0., 20.),
+            Align2::CENTER_CENTER,
+            format!("{}: {title}", scenario.name),
+            FontId::proportional(28.),
+            status_color(outcome),
+        );
+        painter.text(
+            bg.center() + vec2(0., 20.),
+            Align2::CENTER_CENTER,
+            summary,
+            FontId::proportional(16.),
+            Color32::WHITE,
+        );
+    }
+}
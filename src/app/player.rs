@@ -0,0 +1,57 @@
+//! Keyboard control of a raccoon, which stands in for its script.
+
+use eframe::{
+    egui::{self, Key},
+    epaint::vec2,
+};
+
+use crate::{pathfinding::Movement, raccoon::Action};
+
+pub(super) const CONTROLS: &str =
+    "Arrows or WASD move, E eats, F picks up, G drops, H enters or leaves a hole, X digs";
+
+/// The action the keys ask for in this frame, if any. Moves are repeated while the keys are held,
+/// while the other actions need a key press each.
+pub(super) fn player_action(
+    ctx: &egui::Context,
+    movement: Movement,
+    in_hole: bool,
+) -> Option<Action> {
+    ctx.input(|input| {
+        let pressed = |key| input.key_pressed(key);
+        if pressed(Key::E) {
+            return Some(Action::Eat);
+        }
+        if pressed(Key::F) {
+            return Some(Action::PickUp);
+        }
+        if pressed(Key::G) {
+            return Some(Action::Drop);
+        }
+        if pressed(Key::H) {
+            return Some(if in_hole {
+                Action::LeaveHole
+            } else {
+                Action::EnterHole
+            });
+        }
+        if pressed(Key::X) {
+            return Some(Action::Dig);
+        }
+
+        let down = |keys: [Key; 2]| keys.iter().any(|key| input.key_down(*key));
+        let dx = down([Key::ArrowRight, Key::D]) as i32 - down([Key::ArrowLeft, Key::A]) as i32;
+        let dy = down([Key::ArrowDown, Key::S]) as i32 - down([Key::ArrowUp, Key::W]) as i32;
+        // Without diagonal moves, a diagonal key combination moves sideways.
+        let step = match movement {
+            Movement::EightWay => vec2(dx as f32, dy as f32),
+            Movement::FourWay if dx != 0 => vec2(dx as f32, 0.),
+            Movement::FourWay => vec2(0., dy as f32),
+        };
+        movement
+            .directions()
+            .iter()
+            .position(|direction| *direction == step)
+            .map(|direction| Action::Move(direction as u8))
+    })
+}
@@ -0,0 +1,226 @@
+use eframe::egui::{
+    self,
+    plot::{Line, Plot, PlotPoints},
+    Color32, RichText,
+};
+
+use super::{player, RuccoonApp};
+use crate::{
+    pathfinding::{Algorithm, Movement},
+    raccoon::{AgentKind, RestartPolicy, STUCK_TICKS},
+};
+
+/// What the teams are ranked by on the scoreboard.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) enum ScoreRule {
+    /// Corn stored in the team's holes.
+    #[default]
+    Delivered,
+    /// Ticks the team has survived.
+    Survival,
+}
+
+impl ScoreRule {
+    const ALL: [Self; 2] = [Self::Delivered, Self::Survival];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Delivered => "Food delivered",
+            Self::Survival => "Survival",
+        }
+    }
+}
+
+impl RuccoonApp {
+    pub(super) fn show_stats(&mut self, ui: &mut egui::Ui) {
+        if self.scenario.is_some() {
+            self.show_objectives(ui);
+            ui.separator();
+        }
+        if 1 < self.world.teams.len() {
+            self.show_scoreboard(ui);
+            ui.separator();
+        }
+
+        ui.heading("Colony");
+        ui.label(format!("Population: {}", self.world.population()));
+        ui.label(format!("Births: {}", self.world.births));
+        ui.label(format!("Deaths: {}", self.world.deaths));
+
+        let points: PlotPoints = self
+            .world
+            .population_history
+            .iter()
+            .map(|(ticks, population)| [*ticks as f64, *population as f64])
+            .collect();
+        Plot::new("population")
+            .height(150.)
+            .include_y(0.)
+            .allow_drag(false)
+            .allow_zoom(false)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(points).name("population"))
+            });
+
+        ui.separator();
+        let mut algorithm = self.world.shared.path_algorithm.get();
+        egui::ComboBox::from_label("Path finding")
+            .selected_text(algorithm.name())
+            .show_ui(ui, |ui| {
+                for candidate in Algorithm::ALL {
+                    ui.selectable_value(&mut algorithm, candidate, candidate.name());
+                }
+            });
+        self.world.shared.path_algorithm.set(algorithm);
+
+        let mut movement = self.world.shared.movement.get();
+        egui::ComboBox::from_label("Movement")
+            .selected_text(movement.name())
+            .show_ui(ui, |ui| {
+                for candidate in Movement::ALL {
+                    ui.selectable_value(&mut movement, candidate, candidate.name());
+                }
+            });
+        self.world.shared.movement.set(movement);
+
+        let mut traffic = self.world.shared.traffic.get();
+        ui.checkbox(&mut traffic.allow_swaps, "Allow swaps");
+        ui.checkbox(&mut traffic.cooperative, "Cooperative planning");
+        self.world.shared.traffic.set(traffic);
+
+        ui.separator();
+        ui.heading("Raccoons");
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for raccoon in &self.world.raccoons {
+                let state = raccoon.state.borrow();
+                let search = format!(
+                    "  path search: last {} nodes in {:?}, total {} nodes in {:?}",
+                    state.last_search.expanded,
+                    state.last_search.elapsed,
+                    state.total_search.expanded,
+                    state.total_search.elapsed
+                );
+                let stuck = (STUCK_TICKS <= state.stuck).then(|| {
+                    RichText::new(format!("  stuck for {} ticks", state.stuck)).color(Color32::RED)
+                });
+                if raccoon.kind == AgentKind::Predator {
+                    ui.label(RichText::new(format!("Predator {}", raccoon.id)).color(Color32::RED));
+                    ui.label(format!("  caught {}", state.caught));
+                    ui.label(search);
+                    if let Some(stuck) = stuck {
+                        ui.label(stuck);
+                    }
+                    continue;
+                }
+                let player = self.world.shared.player.get() == Some(raccoon.id);
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(format!("Raccoon {}", raccoon.id)).color(state.tint));
+                    let label = if player { "Stop playing" } else { "Play" };
+                    if ui.small_button(label).clicked() {
+                        let shared = &self.world.shared;
+                        shared.player.set((!player).then_some(raccoon.id));
+                        shared.player_action.set(None);
+                    }
+                });
+                if player {
+                    ui.label(RichText::new(format!("  {}", player::CONTROLS)).italics());
+                }
+                if 1 < self.world.teams.len() {
+                    ui.label(format!("  team {}", self.world.teams[state.team].name));
+                }
+                ui.label(format!(
+                    "  age {}, generation {}, satiety {:.2}",
+                    state.age, state.generation, state.satiety
+                ));
+                ui.label(format!(
+                    "  ate {}, delivered {}, carrying {}",
+                    state.ate, state.delivered, state.carrying
+                ));
+                ui.label(search);
+                if let Some(stuck) = stuck {
+                    ui.label(stuck);
+                }
+                if 1 < state.task_count {
+                    ui.label(format!("  running {} tasks", state.task_count));
+                }
+                if 0 < state.preempted {
+                    ui.label(
+                        RichText::new(format!("  pre-empted {} times", state.preempted))
+                            .color(Color32::RED),
+                    );
+                }
+                if 0 < state.restarts {
+                    ui.label(format!("  script restarted {} times", state.restarts));
+                }
+                if state.halted {
+                    ui.label(RichText::new("  script halted").color(Color32::RED));
+                }
+                if let Some(error) = state.error_log.back() {
+                    ui.label(RichText::new(format!("  {error}")).color(Color32::RED));
+                    egui::CollapsingHeader::new(format!("  {} errors", state.script_errors))
+                        .id_source(("errors", raccoon.id))
+                        .show(ui, |ui| {
+                            for error in state.error_log.iter().rev() {
+                                ui.label(error.to_string());
+                            }
+                        });
+                }
+                let mut policy = state.restart_policy;
+                drop(state);
+                ui.horizontal(|ui| {
+                    ui.label("  when the script ends:");
+                    egui::ComboBox::from_id_source(("restart_policy", raccoon.id))
+                        .selected_text(policy.name())
+                        .show_ui(ui, |ui| {
+                            for candidate in RestartPolicy::ALL {
+                                ui.selectable_value(&mut policy, candidate, candidate.name());
+                            }
+                        });
+                });
+                let mut state = raccoon.state.borrow_mut();
+                if state.restart_policy != policy {
+                    state.restart_policy = policy;
+                    // A halted script runs again once it is allowed to restart.
+                    if policy == RestartPolicy::Restart {
+                        state.halted = false;
+                    }
+                }
+            }
+        });
+    }
+
+    fn show_scoreboard(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Scoreboard");
+        egui::ComboBox::from_label("Score")
+            .selected_text(self.score_rule.name())
+            .show_ui(ui, |ui| {
+                for candidate in ScoreRule::ALL {
+                    ui.selectable_value(&mut self.score_rule, candidate, candidate.name());
+                }
+            });
+
+        let stats = self.world.team_stats();
+        let score = |i: usize| match self.score_rule {
+            ScoreRule::Delivered => stats[i].delivered,
+            ScoreRule::Survival => stats[i].survival,
+        };
+        let mut ranking: Vec<usize> = (0..stats.len()).collect();
+        ranking.sort_by_key(|i| std::cmp::Reverse(score(*i)));
+        for (rank, i) in ranking.into_iter().enumerate() {
+            let team = &self.world.teams[i];
+            ui.label(
+                RichText::new(format!("{}. {}: {}", rank + 1, team.name, score(i)))
+                    .color(team.color),
+            );
+            let status = if 0 < stats[i].alive {
+                format!("{} alive", stats[i].alive)
+            } else {
+                "extinct".to_string()
+            };
+            ui.label(format!(
+                "  {status}, ate {}, delivered {}, survived {} ticks",
+                stats[i].eaten, stats[i].delivered, stats[i].survival
+            ));
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! A single cache of all the textures the board is drawn with, keyed by asset id.
+
+use std::collections::HashMap;
+
+use eframe::{
+    egui::{self, Painter},
+    epaint::{pos2, Color32, ColorImage, Rect, TextureId, Vec2},
+};
+
+use super::error_overlay::{ErrorOverlay, ErrorSource};
+use crate::assets::Assets;
+
+const TEXTURE_OPTIONS: egui::TextureOptions = egui::TextureOptions {
+    magnification: egui::TextureFilter::Nearest,
+    minification: egui::TextureFilter::Linear,
+};
+
+/// Side length of the atlas texture in pixels. Images that do not fit get their own texture.
+const ATLAS_SIZE: usize = 1024;
+
+/// Transparent pixels between images in the atlas, so that filtering does not bleed into the
+/// neighbours.
+const ATLAS_PADDING: usize = 1;
+
+/// An image as a part of a texture.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sprite {
+    pub texture: TextureId,
+    /// Where the image is in the texture.
+    pub uv: Rect,
+    /// Size of the image in pixels.
+    pub size: Vec2,
+}
+
+impl Sprite {
+    /// Map a rectangle in the image's own texture coordinates, from 0 to 1, into the texture.
+    pub(crate) fn uv_of(&self, rect: Rect) -> Rect {
+        let map = |p: egui::Pos2| {
+            pos2(
+                self.uv.min.x + p.x * self.uv.width(),
+                self.uv.min.y + p.y * self.uv.height(),
+            )
+        };
+        Rect::from_min_max(map(rect.min), map(rect.max))
+    }
+
+    /// Draw the part `uv` of the image, in its own texture coordinates, into `rect` on the
+    /// screen.
+    pub(crate) fn paint(&self, painter: &Painter, rect: Rect, uv: Rect, tint: Color32) {
+        painter.image(self.texture, rect, self.uv_of(uv), tint);
+    }
+}
+
+/// Images packed into a single texture, in rows of images called shelves.
+struct Atlas {
+    image: ColorImage,
+    handle: Option<egui::TextureHandle>,
+    /// Where the next image goes.
+    cursor: [usize; 2],
+    shelf_height: usize,
+}
+
+impl Atlas {
+    fn new() -> Self {
+        Self {
+            image: ColorImage::new([ATLAS_SIZE, ATLAS_SIZE], Color32::TRANSPARENT),
+            handle: None,
+            cursor: [0, 0],
+            shelf_height: 0,
+        }
+    }
+
+    /// Find room for an image of `size` pixels and return its top left corner.
+    fn allocate(&mut self, size: [usize; 2]) -> Option<[usize; 2]> {
+        let [w, h] = [size[0] + ATLAS_PADDING, size[1] + ATLAS_PADDING];
+        if ATLAS_SIZE < self.cursor[0] + w {
+            self.cursor = [0, self.cursor[1] + self.shelf_height];
+            self.shelf_height = 0;
+        }
+        if ATLAS_SIZE < self.cursor[0] + w || ATLAS_SIZE < self.cursor[1] + h {
+            return None;
+        }
+        let pos = self.cursor;
+        self.cursor[0] += w;
+        self.shelf_height = self.shelf_height.max(h);
+        Some(pos)
+    }
+
+    fn insert(&mut self, image: &ColorImage, ctx: &egui::Context) -> Option<Sprite> {
+        let [x, y] = self.allocate(image.size)?;
+        let [w, h] = image.size;
+        for row in 0..h {
+            let dst = (y + row) * ATLAS_SIZE + x;
+            self.image.pixels[dst..dst + w].copy_from_slice(&image.pixels[row * w..(row + 1) * w]);
+        }
+        let texture = match &mut self.handle {
+            Some(handle) => {
+                handle.set(self.image.clone(), TEXTURE_OPTIONS);
+                handle.id()
+            }
+            None => {
+                let handle = ctx.load_texture("atlas", self.image.clone(), TEXTURE_OPTIONS);
+                self.handle.insert(handle).id()
+            }
+        };
+        let size = ATLAS_SIZE as f32;
+        Some(Sprite {
+            texture,
+            uv: Rect::from_min_max(
+                pos2(x as f32 / size, y as f32 / size),
+                pos2((x + w) as f32 / size, (y + h) as f32 / size),
+            ),
+            size: Vec2::new(w as f32, h as f32),
+        })
+    }
+}
+
+pub(crate) struct Textures {
+    /// Loaded sprites by asset id, or `None` if loading failed, so that it is not retried every
+    /// frame.
+    sprites: HashMap<String, Option<Sprite>>,
+    /// Textures of the images that are not in the atlas, which have to be kept alive.
+    handles: Vec<egui::TextureHandle>,
+    atlas: Option<Atlas>,
+}
+
+impl Textures {
+    pub(crate) fn new(use_atlas: bool) -> Self {
+        Self {
+            sprites: HashMap::new(),
+            handles: vec![],
+            atlas: use_atlas.then(Atlas::new),
+        }
+    }
+
+    /// The sprite of the image asset `id`, loading it on the first use.
+    pub(crate) fn get(
+        &mut self,
+        id: &str,
+        assets: &Assets,
+        errors: &mut ErrorOverlay,
+        ctx: &egui::Context,
+    ) -> Option<Sprite> {
+        if let Some(sprite) = self.sprites.get(id) {
+            return *sprite;
+        }
+        let sprite = match load_image(id, assets) {
+            Ok(image) => Some(self.upload(id, image, ctx)),
+            Err(e) => {
+                errors.report(
+                    ErrorSource::Assets,
+                    format!("Failed to load image `{id}`: {e}"),
+                );
+                None
+            }
+        };
+        self.sprites.insert(id.to_string(), sprite);
+        sprite
+    }
+
+    /// Forget all textures, so that they are loaded again from the assets on the next use.
+    pub(crate) fn reload(&mut self) {
+        self.sprites.clear();
+        self.handles.clear();
+        if let Some(atlas) = &mut self.atlas {
+            *atlas = Atlas::new();
+        }
+    }
+
+    fn upload(&mut self, id: &str, image: ColorImage, ctx: &egui::Context) -> Sprite {
+        if let Some(sprite) = self
+            .atlas
+            .as_mut()
+            .and_then(|atlas| atlas.insert(&image, ctx))
+        {
+            return sprite;
+        }
+        let size = Vec2::new(image.size[0] as f32, image.size[1] as f32);
+        let handle = ctx.load_texture(id, image, TEXTURE_OPTIONS);
+        let sprite = Sprite {
+            texture: handle.id(),
+            uv: Rect::from_min_max(pos2(0., 0.), pos2(1., 1.)),
+            size,
+        };
+        self.handles.push(handle);
+        sprite
+    }
+}
+
+fn load_image(id: &str, assets: &Assets) -> Result<ColorImage, Box<dyn std::error::Error>> {
+    let img = image::load_from_memory(&assets.get(id)?)?.into_rgba8();
+    let size = [img.width() as usize, img.height() as usize];
+    Ok(ColorImage::from_rgba_unmultiplied(size, &img.into_raw()))
+}
@@ -0,0 +1,134 @@
+//! Finding the assets and scripts the game needs, so that the binary works from any directory.
+//!
+//! Every asset has a name in `assets/manifest.ini` that maps to a path relative to a data root,
+//! which is a directory laid out like this repository. The roots are searched in this order, and
+//! the copy built into the binary is used if none of them has the file:
+//!
+//! 1. the directory given with `--assets`
+//! 2. the directory of the executable and a few above it, which covers `target/debug`
+//! 3. the current directory
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::ini;
+
+const MANIFEST: &str = "assets/manifest.ini";
+
+/// Default assets built into the binary, by their paths.
+const EMBEDDED: &[(&str, &[u8])] = &[
+    (MANIFEST, include_bytes!("../assets/manifest.ini")),
+    ("assets/dirt.png", include_bytes!("../assets/dirt.png")),
+    ("assets/weeds.png", include_bytes!("../assets/weeds.png")),
+    ("assets/water.png", include_bytes!("../assets/water.png")),
+    ("assets/mud.png", include_bytes!("../assets/mud.png")),
+    ("assets/road.png", include_bytes!("../assets/road.png")),
+    ("assets/wall.png", include_bytes!("../assets/wall.png")),
+    ("assets/hole.png", include_bytes!("../assets/hole.png")),
+    ("assets/corn.png", include_bytes!("../assets/corn.png")),
+    (
+        "assets/raccoon_walk.png",
+        include_bytes!("../assets/raccoon_walk.png"),
+    ),
+    (
+        "assets/dog_walk.png",
+        include_bytes!("../assets/dog_walk.png"),
+    ),
+    (
+        "assets/tilesets.ini",
+        include_bytes!("../assets/tilesets.ini"),
+    ),
+    (
+        "scripts/raccoon.rscl",
+        include_bytes!("../scripts/raccoon.rscl"),
+    ),
+    ("scripts/dog.rscl", include_bytes!("../scripts/dog.rscl")),
+];
+
+/// Number of directories above the executable that are searched.
+const EXE_ANCESTORS: usize = 3;
+
+pub(crate) struct Assets {
+    roots: Vec<PathBuf>,
+    /// Asset names to paths relative to a root.
+    manifest: HashMap<String, String>,
+}
+
+impl Assets {
+    pub(crate) fn new(override_dir: Option<&Path>) -> Result<Self, String> {
+        let mut roots = vec![];
+        roots.extend(override_dir.map(Path::to_path_buf));
+        if let Ok(exe) = std::env::current_exe() {
+            roots.extend(
+                exe.ancestors()
+                    .skip(1)
+                    .take(EXE_ANCESTORS)
+                    .map(Path::to_path_buf),
+            );
+        }
+        if let Ok(cwd) = std::env::current_dir() {
+            roots.push(cwd);
+        }
+        Self::with_roots(roots)
+    }
+
+    /// Only the assets built into the binary, for when the manifest on disk is broken.
+    pub(crate) fn embedded() -> Self {
+        Self::with_roots(vec![]).expect("The embedded manifest is valid")
+    }
+
+    fn with_roots(roots: Vec<PathBuf>) -> Result<Self, String> {
+        let mut assets = Self {
+            roots,
+            manifest: HashMap::new(),
+        };
+        let src = assets.read_to_string(MANIFEST)?;
+        for section in ini::parse(&src).map_err(|e| format!("{MANIFEST}:{e}"))? {
+            for entry in section.entries {
+                assets.manifest.insert(entry.key, entry.value);
+            }
+        }
+        Ok(assets)
+    }
+
+    /// Read a file by its path relative to the data roots.
+    pub(crate) fn read(&self, path: &str) -> Result<Cow<'static, [u8]>, String> {
+        for root in &self.roots {
+            let full = root.join(path);
+            if full.is_file() {
+                return std::fs::read(&full)
+                    .map(Cow::Owned)
+                    .map_err(|e| format!("{}: {e}", full.display()));
+            }
+        }
+        EMBEDDED
+            .iter()
+            .find(|(embedded, _)| *embedded == path)
+            .map(|(_, data)| Cow::Borrowed(*data))
+            .ok_or_else(|| {
+                let roots: Vec<_> = self.roots.iter().map(|root| root.display()).collect();
+                format!("{path} was not found in any of {roots:?}")
+            })
+    }
+
+    pub(crate) fn read_to_string(&self, path: &str) -> Result<String, String> {
+        let data = self.read(path)?;
+        String::from_utf8(data.into_owned()).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// The path of an asset by its name in the manifest.
+    pub(crate) fn path(&self, name: &str) -> Result<&str, String> {
+        self.manifest
+            .get(name)
+            .map(|path| path.as_str())
+            .ok_or_else(|| format!("`{name}` is not in {MANIFEST}"))
+    }
+
+    /// Read an asset by its name in the manifest.
+    pub(crate) fn get(&self, name: &str) -> Result<Cow<'static, [u8]>, String> {
+        self.read(self.path(name)?)
+    }
+}
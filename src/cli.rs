@@ -0,0 +1,226 @@
+//! Command line options.
+
+use std::path::PathBuf;
+
+use crate::{raccoon::RestartPolicy, scenario::Scenario};
+
+pub(crate) const USAGE: &str = "\
+Usage: ruccoon [options] [script]...
+       ruccoon run [options] [script]...
+       ruccoon sweep [options] [script]...
+       ruccoon tournament [options] <script>...
+
+Commands:
+  run               Simulate without a window and print a summary
+  sweep             Simulate many worlds in parallel and print a table of the results
+  tournament        Rank scripts by how well their raccoons do over the same worlds
+
+Arguments:
+  script            Raccoon script to run instead of the default one. With more than one,
+                    each script makes a team of raccoons in the same world
+
+Options:
+  --script <file>   Same as a script argument
+  --assets <dir>    Directory to look for assets in before the default ones
+  --atlas           Pack the images into a single texture
+  --play            Control the first raccoon with the keyboard
+  --on-script-end <policy>
+                    What a raccoon does when its script ends or fails: restart it, halt
+                    or die (default: restart, or as the scenario says)
+  --director <file> World script that stages events such as corn growing or walls going up
+  --scenario <file> Play a scenario with its own map, scripts and objectives. A run fails
+                    unless the objectives are met
+  --seed <n>        Seed of the random number generator, to reproduce a world
+  --step-budget <n> Steps a script can take per tick before it is pre-empted: native calls
+                    and passes through a loop or a function (default: 1000)
+  --ticks <n>       Number of ticks to simulate with run and sweep (default: the time limit
+                    of the scenario, or 10000)
+  --report <file>   Write the summary of run or sweep as JSON to a file
+  -q, --quiet       Do not print the events of the agents' lives
+  --seeds <seeds>   Seeds of the worlds of sweep and tournament, either a range like 0..10
+                    or a list like 1,5,7 (default 0..10)
+  --param <p>=<v>   Values of a colony parameter to sweep, like hunger_rate=0.003,0.005.
+                    Can be given multiple times to sweep all the combinations
+  --threads <n>     Number of worlds to simulate at once (default: number of CPUs)
+  --versus          Put all the scripts of a tournament in each world, instead of giving
+                    each script worlds of its own
+  --replays <dir>   Write a replay of every tournament match to a directory
+  -d, --debug       Print debug output of the scripts
+  --disasm          Print the disassembled bytecode of the scripts
+  -h, --help        Print this help";
+
+const DEFAULT_TICKS: usize = 10000;
+const DEFAULT_SEEDS: std::ops::Range<u64> = 0..10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Command {
+    /// Open the window.
+    #[default]
+    Gui,
+    /// Run a fixed number of ticks without a window.
+    Run,
+    /// Run many worlds without a window and compare them.
+    Sweep,
+    /// Run many worlds without a window and compare the scripts.
+    Tournament,
+}
+
+#[derive(Clone)]
+pub(crate) struct Options {
+    pub command: Command,
+    pub scripts: Vec<String>,
+    pub assets: Option<PathBuf>,
+    pub atlas: bool,
+    pub play: bool,
+    /// The restart policy of the raccoons, overriding the scenario's.
+    pub restart_policy: Option<RestartPolicy>,
+    /// The world script, overriding the scenario's.
+    pub director: Option<String>,
+    pub scenario: Option<PathBuf>,
+    pub seed: Option<u64>,
+    /// Steps per tick of every script, overriding the scenario's.
+    pub step_budget: Option<usize>,
+    /// Ticks to simulate without a window, if not the default. See `Options::ticks`.
+    pub ticks: Option<usize>,
+    pub report: Option<PathBuf>,
+    pub quiet: bool,
+    pub seeds: Vec<u64>,
+    /// Colony parameters to sweep, with the values to try for each.
+    pub params: Vec<(String, Vec<f64>)>,
+    pub threads: Option<usize>,
+    pub versus: bool,
+    pub replays: Option<PathBuf>,
+    pub debug_output: bool,
+    pub disasm: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            command: Command::default(),
+            scripts: vec![],
+            assets: None,
+            atlas: false,
+            play: false,
+            restart_policy: None,
+            director: None,
+            scenario: None,
+            seed: None,
+            step_budget: None,
+            ticks: None,
+            report: None,
+            quiet: false,
+            seeds: DEFAULT_SEEDS.collect(),
+            params: vec![],
+            threads: None,
+            versus: false,
+            replays: None,
+            debug_output: false,
+            disasm: false,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parse the arguments, not including the program name.
+    pub(crate) fn parse(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut args = args.peekable();
+        let mut options = Self::default();
+        match args.peek().map(String::as_str) {
+            Some("run") => options.command = Command::Run,
+            Some("sweep") => options.command = Command::Sweep,
+            Some("tournament") => options.command = Command::Tournament,
+            _ => {}
+        }
+        if options.command != Command::Gui {
+            args.next();
+        }
+        while let Some(arg) = args.next() {
+            let mut value = |what: &str| args.next().ok_or(format!("{arg} needs {what}"));
+            match arg.as_str() {
+                "--script" => options.scripts.push(value("a file")?),
+                "--assets" => options.assets = Some(PathBuf::from(value("a directory")?)),
+                "--atlas" => options.atlas = true,
+                "--play" => options.play = true,
+                "--on-script-end" => {
+                    let policy = value("a policy")?;
+                    options.restart_policy =
+                        Some(policy.parse().map_err(|e| format!("{arg}: {e}"))?);
+                }
+                "--director" => options.director = Some(value("a file")?),
+                "--scenario" => options.scenario = Some(PathBuf::from(value("a file")?)),
+                "--seed" => options.seed = Some(parse_number(&arg, &value("a number")?)?),
+                "--step-budget" => {
+                    options.step_budget = Some(parse_number(&arg, &value("a number")?)?);
+                }
+                "--ticks" => options.ticks = Some(parse_number(&arg, &value("a number")?)?),
+                "--report" => options.report = Some(PathBuf::from(value("a file")?)),
+                "-q" | "--quiet" => options.quiet = true,
+                "--seeds" => options.seeds = parse_seeds(&value("seeds")?)?,
+                "--param" => options.params.push(parse_param(&value("a parameter")?)?),
+                "--threads" => {
+                    options.threads = Some(parse_number(&arg, &value("a number")?)?);
+                }
+                "--versus" => options.versus = true,
+                "--replays" => options.replays = Some(PathBuf::from(value("a directory")?)),
+                "-d" | "--debug" => options.debug_output = true,
+                "--disasm" => options.disasm = true,
+                "-h" | "--help" => options.help = true,
+                _ if arg.starts_with('-') => return Err(format!("unknown option {arg}")),
+                _ => options.scripts.push(arg),
+            }
+        }
+        Ok(options)
+    }
+
+    /// Number of ticks to simulate without a window: as given, or else until the scenario's time
+    /// limit.
+    pub(crate) fn ticks(&self, scenario: Option<&Scenario>) -> usize {
+        self.ticks
+            .or(scenario.map(|scenario| scenario.time_limit))
+            .unwrap_or(DEFAULT_TICKS)
+    }
+
+    /// Load the scenario given with `--scenario`, if any.
+    pub(crate) fn load_scenario(&self) -> Result<Option<Scenario>, String> {
+        self.scenario.as_deref().map(Scenario::load).transpose()
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{option} needs a number, not {value}"))
+}
+
+/// Parse a range of seeds like `0..10`, or a list like `1,5,7`.
+fn parse_seeds(value: &str) -> Result<Vec<u64>, String> {
+    let seeds: Vec<u64> = if let Some((start, end)) = value.split_once("..") {
+        let start: u64 = parse_number("--seeds", start)?;
+        let end: u64 = parse_number("--seeds", end)?;
+        (start..end).collect()
+    } else {
+        value
+            .split(',')
+            .map(|seed| parse_number("--seeds", seed.trim()))
+            .collect::<Result<_, _>>()?
+    };
+    if seeds.is_empty() {
+        return Err(format!("--seeds {value} has no seeds"));
+    }
+    Ok(seeds)
+}
+
+/// Parse a parameter to sweep, like `hunger_rate=0.003,0.005`.
+fn parse_param(value: &str) -> Result<(String, Vec<f64>), String> {
+    let (name, values) = value.split_once('=').ok_or(format!(
+        "--param needs a name and values, like hunger_rate=0.005, not {value}"
+    ))?;
+    let values = values
+        .split(',')
+        .map(|v| parse_number("--param", v.trim()))
+        .collect::<Result<_, _>>()?;
+    Ok((name.trim().to_string(), values))
+}
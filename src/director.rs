@@ -0,0 +1,236 @@
+//! The director: an optional world script that stages events, such as where corn grows or when
+//! a wall goes up, so that scenarios can script them without changing the game.
+//!
+//! The director runs on a VM of its own, resumed once per tick at the start of the tick until it
+//! yields, like a raccoon script, and is pre-empted at checkpoints like one. It sees the world
+//! through its own set of native functions.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use eframe::epaint::pos2;
+use rand::Rng;
+use ruscal::{
+    ast::TypeDecl,
+    bytecode::NativeFn,
+    value::Value,
+    vm::{Vm, YieldResult},
+};
+
+use crate::{
+    raccoon::{checkpoint_location, compile_with_natives, logging_events, Location, CHECKPOINT_FN},
+    world::{MapCell, SharedWorld, BOARD_SIZE},
+};
+
+/// What the director's native functions can see, shared between the VM and the `Director`.
+struct DirectorData {
+    world: SharedWorld,
+    /// The number of ticks simulated so far, as returned by `tick()`.
+    ticks: Cell<usize>,
+    /// Steps taken since the director was resumed.
+    steps: Cell<usize>,
+    /// Steps the director can take per tick before it is pre-empted.
+    step_budget: usize,
+    /// Whether the script is yielding from a checkpoint because it ran out of steps.
+    preempting: Cell<bool>,
+    /// The checkpoint the script passed last, where its errors are reported.
+    checkpoint: Cell<Option<Location>>,
+    /// Scripts of the raccoons to spawn once the director yields, as given to `spawn_raccoon`.
+    spawns: RefCell<Vec<String>>,
+}
+
+pub(crate) struct Director {
+    /// The file name of the script.
+    pub name: String,
+    vm: Vm,
+    data: Rc<DirectorData>,
+    /// Whether the script has run to its end, or failed. A finished director stays quiet.
+    finished: bool,
+}
+
+impl Director {
+    /// Compile the director script `source`, where `name` is its file name.
+    pub(crate) fn new(
+        name: &str,
+        source: &str,
+        world: &SharedWorld,
+        step_budget: usize,
+        disasm: bool,
+        debug_output: bool,
+    ) -> Result<Self, String> {
+        let bytecode = compile_with_natives(name, source, disasm, extend_funcs)
+            .map_err(|e| format!("Director compile error: {e}"))?;
+        let data = Rc::new(DirectorData {
+            world: world.clone(),
+            ticks: Cell::new(0),
+            steps: Cell::new(0),
+            step_budget,
+            preempting: Cell::new(false),
+            checkpoint: Cell::new(None),
+            spawns: RefCell::new(vec![]),
+        });
+        let mut vm = Vm::new(Rc::new(bytecode), Box::new(data.clone()), debug_output);
+        vm.init_fn("main", &[])
+            .map_err(|e| format!("{name}: init_fn: {e}"))?;
+        Ok(Self {
+            name: name.to_string(),
+            vm,
+            data,
+            finished: false,
+        })
+    }
+
+    /// Resume the script until it yields, and return the scripts of the raccoons it asked to
+    /// spawn in the meantime.
+    pub(crate) fn run(&mut self, ticks: usize) -> Vec<String> {
+        if self.finished {
+            return vec![];
+        }
+        self.data.ticks.set(ticks);
+        self.data.steps.set(0);
+        match self.vm.interpret() {
+            Ok(YieldResult::Suspend(_)) => {
+                // Like a raccoon's, the script carries on from the checkpoint in the next tick.
+                if self.data.preempting.take() && logging_events() {
+                    eprintln!(
+                        "Director {} exceeded its step budget of {} and was pre-empted",
+                        self.name, self.data.step_budget
+                    );
+                }
+            }
+            Ok(YieldResult::Finished(_)) => self.finished = true,
+            Err(e) => {
+                if logging_events() {
+                    match self.data.checkpoint.get() {
+                        Some(location) => eprintln!(
+                            "Error in director {}, in the block at {location}: {e}",
+                            self.name
+                        ),
+                        None => eprintln!("Error in director {}: {e}", self.name),
+                    }
+                }
+                self.finished = true;
+            }
+        }
+        self.data.spawns.take()
+    }
+}
+
+/// Downcast the VM's user data and charge one step against the director's budget.
+fn director_data(data: &dyn std::any::Any) -> Option<&DirectorData> {
+    let data = data.downcast_ref::<Rc<DirectorData>>()?;
+    data.steps.set(data.steps.get() + 1);
+    Some(data)
+}
+
+/// Read the `idx`-th argument of a native function as a cell index of the map.
+fn arg_cell(args: &[Value], idx: usize) -> Option<usize> {
+    let x = args.get(idx)?.coerce_i64().ok()?;
+    let y = args.get(idx + 1)?.coerce_i64().ok()?;
+    let size = BOARD_SIZE as i64;
+    ((0..size).contains(&x) && (0..size).contains(&y)).then_some((x + y * size) as usize)
+}
+
+/// A native function of the director that returns an integer.
+fn director_fn(
+    args: Vec<(&'static str, TypeDecl)>,
+    f: fn(&DirectorData, &[Value]) -> i64,
+) -> NativeFn<'static> {
+    NativeFn::new(
+        args,
+        TypeDecl::I64,
+        Box::new(move |data, args| Value::I64(director_data(data).map_or(0, |data| f(data, args)))),
+    )
+}
+
+fn extend_funcs(proc: &mut dyn FnMut(String, NativeFn<'static>)) {
+    proc(
+        CHECKPOINT_FN.to_string(),
+        director_fn(
+            vec![("line", TypeDecl::I64), ("column", TypeDecl::I64)],
+            |data, args| {
+                data.checkpoint.set(checkpoint_location(args));
+                data.preempting.set(data.step_budget < data.steps.get());
+                data.preempting.get() as i64
+            },
+        ),
+    );
+    proc(
+        "tick".to_string(),
+        director_fn(vec![], |data, _| data.ticks.get() as i64),
+    );
+    proc(
+        "item_count".to_string(),
+        director_fn(vec![], |data, _| data.world.items.borrow().len() as i64),
+    );
+    proc(
+        "random".to_string(),
+        director_fn(vec![("n", TypeDecl::I64)], |data, args| {
+            let n = args.first().and_then(|n| n.coerce_i64().ok()).unwrap_or(0);
+            if n <= 0 {
+                return 0;
+            }
+            data.world.rng.borrow_mut().gen_range(0..n)
+        }),
+    );
+    proc(
+        "spawn_corn".to_string(),
+        director_fn(
+            vec![("x", TypeDecl::I64), ("y", TypeDecl::I64)],
+            |data, args| {
+                let Some(cell) = arg_cell(args, 0) else {
+                    return 0;
+                };
+                let pos = pos2((cell % BOARD_SIZE) as f32, (cell / BOARD_SIZE) as f32);
+                let world = &data.world;
+                let mut items = world.items.borrow_mut();
+                if !world.map.borrow()[cell].is_passable() || items.contains(&pos) {
+                    return 0;
+                }
+                items.push(pos);
+                1
+            },
+        ),
+    );
+    proc(
+        "set_wall".to_string(),
+        director_fn(
+            vec![("x", TypeDecl::I64), ("y", TypeDecl::I64)],
+            |data, args| {
+                let Some(cell) = arg_cell(args, 0) else {
+                    return 0;
+                };
+                let pos = pos2((cell % BOARD_SIZE) as f32, (cell / BOARD_SIZE) as f32);
+                // Walling in an agent, a hole or a corn would leave it stuck in the wall.
+                let world = &data.world;
+                let taken = world.agents.borrow().iter().any(|agent| agent.pos == pos)
+                    || world.holes.borrow().iter().any(|hole| hole.pos == pos)
+                    || world.items.borrow().contains(&pos);
+                if taken {
+                    return 0;
+                }
+                world.map.borrow_mut()[cell] = MapCell::Wall;
+                1
+            },
+        ),
+    );
+    proc(
+        "spawn_raccoon".to_string(),
+        NativeFn::new(
+            vec![("script", TypeDecl::Str)],
+            TypeDecl::I64,
+            Box::new(|data, args| {
+                let Some(data) = director_data(data) else {
+                    return Value::I64(0);
+                };
+                let Some(Value::Str(script)) = args.first() else {
+                    return Value::I64(0);
+                };
+                data.spawns.borrow_mut().push(script.clone());
+                Value::I64(1)
+            }),
+        ),
+    );
+}
@@ -0,0 +1,617 @@
+//! Running the simulation without a window, to measure how well a script does.
+
+use std::{
+    cell::RefCell,
+    fmt::Write,
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::Path,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{
+    assets::Assets,
+    cli::Options,
+    raccoon::{set_logging_events, AgentKind, RaccoonState},
+    scenario::{Progress, Scenario, Status},
+    world::{MapCell, World, BOARD_SIZE},
+};
+
+/// What a raccoon achieved over its life, or so far if it is still alive.
+pub(crate) struct RaccoonSummary {
+    id: usize,
+    pub team: usize,
+    generation: usize,
+    alive: bool,
+    born: usize,
+    age: usize,
+    corn_eaten: usize,
+    pub collected: usize,
+    pub delivered: usize,
+    satiety_sum: f64,
+    starving_ticks: usize,
+    script_errors: usize,
+    preempted: usize,
+    restarts: usize,
+}
+
+impl RaccoonSummary {
+    fn new(id: usize, state: &RefCell<RaccoonState>) -> Self {
+        let state = state.borrow();
+        Self {
+            id,
+            team: state.team,
+            generation: state.generation,
+            alive: !state.dead,
+            born: state.born,
+            age: state.age,
+            corn_eaten: state.ate,
+            collected: state.collected,
+            delivered: state.delivered,
+            satiety_sum: state.satiety_sum,
+            starving_ticks: state.starving_total,
+            script_errors: state.script_errors,
+            preempted: state.preempted,
+            restarts: state.restarts,
+        }
+    }
+
+    /// The tick the raccoon died in, or `None` if it is still alive.
+    pub(crate) fn died(&self) -> Option<usize> {
+        (!self.alive).then_some(self.born + self.age)
+    }
+}
+
+/// How a run did with the objectives of its scenario.
+pub(crate) struct ScenarioResult {
+    name: String,
+    /// The objectives, described, and how each turned out.
+    objectives: Vec<(String, Status)>,
+    pub outcome: Status,
+}
+
+impl ScenarioResult {
+    fn new(scenario: &Scenario, progress: &Progress) -> Self {
+        Self {
+            name: scenario.name.clone(),
+            objectives: scenario
+                .objectives
+                .iter()
+                .map(|objective| objective.describe())
+                .zip(progress.statuses.iter().copied())
+                .collect(),
+            outcome: progress.outcome(),
+        }
+    }
+}
+
+/// The results of a run, written as JSON with `--report`.
+pub(crate) struct Report {
+    seed: Option<u64>,
+    scripts: Vec<String>,
+    pub ticks: usize,
+    pub population: usize,
+    births: usize,
+    pub deaths: usize,
+    pub raccoons: Vec<RaccoonSummary>,
+    pub scenario: Option<ScenarioResult>,
+    /// Problems setting up the world, such as a script that does not compile.
+    pub errors: Vec<String>,
+}
+
+impl Report {
+    fn new(
+        options: &Options,
+        world: &World,
+        scenario: Option<ScenarioResult>,
+        errors: Vec<String>,
+    ) -> Self {
+        let mut raccoons: Vec<_> = world
+            .departed
+            .iter()
+            .filter(|departed| departed.kind == AgentKind::Raccoon)
+            .map(|departed| RaccoonSummary::new(departed.id, &departed.state))
+            .chain(
+                world
+                    .raccoons
+                    .iter()
+                    .filter(|raccoon| raccoon.kind == AgentKind::Raccoon)
+                    .map(|raccoon| RaccoonSummary::new(raccoon.id, &raccoon.state)),
+            )
+            .collect();
+        raccoons.sort_by_key(|raccoon| raccoon.id);
+        Self {
+            seed: options.seed,
+            scripts: world.teams.iter().map(|team| team.name.clone()).collect(),
+            ticks: world.ticks(),
+            population: world.population(),
+            births: world.births,
+            deaths: world.deaths,
+            raccoons,
+            scenario,
+            errors,
+        }
+    }
+
+    pub(crate) fn corn_eaten(&self) -> usize {
+        self.raccoons.iter().map(|raccoon| raccoon.corn_eaten).sum()
+    }
+
+    pub(crate) fn delivered(&self) -> usize {
+        self.raccoons.iter().map(|raccoon| raccoon.delivered).sum()
+    }
+
+    /// Satiety averaged over every tick every raccoon lived.
+    pub(crate) fn average_satiety(&self) -> f64 {
+        let ticks: usize = self.raccoons.iter().map(|raccoon| raccoon.age).sum();
+        let sum: f64 = self
+            .raccoons
+            .iter()
+            .map(|raccoon| raccoon.satiety_sum)
+            .sum();
+        if ticks == 0 {
+            0.
+        } else {
+            sum / ticks as f64
+        }
+    }
+
+    pub(crate) fn starving_ticks(&self) -> usize {
+        self.raccoons
+            .iter()
+            .map(|raccoon| raccoon.starving_ticks)
+            .sum()
+    }
+
+    pub(crate) fn script_errors(&self) -> usize {
+        self.raccoons
+            .iter()
+            .map(|raccoon| raccoon.script_errors)
+            .sum()
+    }
+
+    pub(crate) fn restarts(&self) -> usize {
+        self.raccoons.iter().map(|raccoon| raccoon.restarts).sum()
+    }
+
+    fn print(&self) {
+        if let Some(seed) = self.seed {
+            println!("Seed: {seed}");
+        }
+        println!("Ticks: {}", self.ticks);
+        println!(
+            "Population: {}, births: {}, deaths: {}",
+            self.population, self.births, self.deaths
+        );
+        println!(
+            "Corn eaten: {}, delivered: {}",
+            self.corn_eaten(),
+            self.delivered()
+        );
+        println!("Average satiety: {:.3}", self.average_satiety());
+        println!("Starvation ticks: {}", self.starving_ticks());
+        println!(
+            "Script errors: {}, restarts: {}",
+            self.script_errors(),
+            self.restarts()
+        );
+        if let Some(scenario) = &self.scenario {
+            println!("Scenario {}: {}", scenario.name, scenario.outcome.name());
+            for (objective, status) in &scenario.objectives {
+                println!("  {objective}: {}", status.name());
+            }
+        }
+        for e in &self.errors {
+            println!("Error: {e}");
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String cannot fail.
+        let _ = self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "{{")?;
+        match self.seed {
+            Some(seed) => writeln!(out, "  \"seed\": {seed},")?,
+            None => writeln!(out, "  \"seed\": null,")?,
+        }
+        let scripts: Vec<_> = self.scripts.iter().map(|s| json_string(s)).collect();
+        writeln!(out, "  \"scripts\": [{}],", scripts.join(", "))?;
+        writeln!(out, "  \"ticks\": {},", self.ticks)?;
+        writeln!(out, "  \"population\": {},", self.population)?;
+        writeln!(out, "  \"births\": {},", self.births)?;
+        writeln!(out, "  \"deaths\": {},", self.deaths)?;
+        writeln!(out, "  \"corn_eaten\": {},", self.corn_eaten())?;
+        writeln!(out, "  \"delivered\": {},", self.delivered())?;
+        writeln!(out, "  \"average_satiety\": {:.4},", self.average_satiety())?;
+        writeln!(out, "  \"starvation_ticks\": {},", self.starving_ticks())?;
+        writeln!(out, "  \"script_errors\": {},", self.script_errors())?;
+        writeln!(out, "  \"restarts\": {},", self.restarts())?;
+        writeln!(out, "  \"raccoons\": [")?;
+        for (i, raccoon) in self.raccoons.iter().enumerate() {
+            let average_satiety = if raccoon.age == 0 {
+                0.
+            } else {
+                raccoon.satiety_sum / raccoon.age as f64
+            };
+            write!(
+                out,
+                "    {{\"id\": {}, \"team\": {}, \"generation\": {}, \"alive\": {}, \
+                \"born\": {}, \"age\": {}, \"corn_eaten\": {}, \"collected\": {}, \
+                \"delivered\": {}, \"average_satiety\": {:.4}, \"starvation_ticks\": {}, \
+                \"script_errors\": {}, \"preempted\": {}, \"restarts\": {}}}",
+                raccoon.id,
+                raccoon.team,
+                raccoon.generation,
+                raccoon.alive,
+                raccoon.born,
+                raccoon.age,
+                raccoon.corn_eaten,
+                raccoon.collected,
+                raccoon.delivered,
+                average_satiety,
+                raccoon.starving_ticks,
+                raccoon.script_errors,
+                raccoon.preempted,
+                raccoon.restarts
+            )?;
+            writeln!(out, "{}", separator(i, self.raccoons.len()))?;
+        }
+        writeln!(out, "  ],")?;
+        match &self.scenario {
+            Some(scenario) => {
+                let objectives: Vec<_> = scenario
+                    .objectives
+                    .iter()
+                    .map(|(objective, status)| {
+                        format!(
+                            "{{\"objective\": {}, \"status\": \"{}\"}}",
+                            json_string(objective),
+                            status.name()
+                        )
+                    })
+                    .collect();
+                writeln!(
+                    out,
+                    "  \"scenario\": {{\"name\": {}, \"outcome\": \"{}\", \"objectives\": [{}]}},",
+                    json_string(&scenario.name),
+                    scenario.outcome.name(),
+                    objectives.join(", ")
+                )?;
+            }
+            None => writeln!(out, "  \"scenario\": null,")?,
+        }
+        writeln!(out, "  \"errors\": [")?;
+        for (i, e) in self.errors.iter().enumerate() {
+            writeln!(
+                out,
+                "    {}{}",
+                json_string(e),
+                separator(i, self.errors.len())
+            )?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")
+    }
+}
+
+/// Build a world, run it for `options.ticks` ticks and report how the raccoons did.
+///
+/// Fails if the world could not be set up as asked, for example if the script does not compile,
+/// so that a broken script fails a CI job. With a scenario, it also fails unless the objectives
+/// were met.
+pub(crate) fn run(options: &Options) -> ExitCode {
+    if options.quiet {
+        set_logging_events(false);
+    }
+    let report = simulate(options, &[]);
+    report.print();
+    if let Some(path) = &options.report {
+        if let Err(e) = std::fs::write(path, report.to_json()) {
+            eprintln!("Failed to write the report to {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let failed = report
+        .scenario
+        .as_ref()
+        .is_some_and(|scenario| scenario.outcome != Status::Succeeded);
+    if report.errors.is_empty() && !failed {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Build a world with the colony parameters `params` set and run it for `options.ticks` ticks.
+pub(crate) fn simulate(options: &Options, params: &[(String, f64)]) -> Report {
+    simulate_with_replay(options, params, None)
+}
+
+/// Simulate like `simulate`, and also record a replay to the file `replay` if given.
+pub(crate) fn simulate_with_replay(
+    options: &Options,
+    params: &[(String, f64)],
+    replay: Option<&Path>,
+) -> Report {
+    let mut errors = vec![];
+    let assets = Assets::new(options.assets.as_deref()).unwrap_or_else(|e| {
+        errors.push(e);
+        Assets::embedded()
+    });
+    let scenario = options.load_scenario().unwrap_or_else(|e| {
+        errors.push(e);
+        None
+    });
+    // Without a seed, draw one, so that the report tells how to reproduce the run.
+    let mut options = options.clone();
+    options.seed = Some(
+        options
+            .seed
+            .or(scenario.as_ref().and_then(|scenario| scenario.seed))
+            .unwrap_or_else(rand::random),
+    );
+    let mut world = World::with_scenario(&options, params, scenario.as_ref(), &assets, &mut errors);
+    let mut progress = scenario.as_ref().map(Progress::new);
+
+    let mut replay = replay.and_then(|path| match Replay::create(path, &world) {
+        Ok(replay) => Some(replay),
+        Err(e) => {
+            errors.push(format!(
+                "Failed to write the replay {}: {e}",
+                path.display()
+            ));
+            None
+        }
+    });
+    for _ in 0..options.ticks(scenario.as_ref()) {
+        world.tick();
+        if let Some(recorder) = &mut replay {
+            if let Err(e) = recorder.record(&world) {
+                errors.push(format!("Failed to write the replay: {e}"));
+                replay = None;
+            }
+        }
+        // There is no point in going on once the scenario is decided.
+        if let (Some(scenario), Some(progress)) = (&scenario, &mut progress) {
+            progress.update(scenario, &world);
+            if progress.outcome() != Status::Pending {
+                break;
+            }
+        }
+    }
+
+    let result = scenario
+        .as_ref()
+        .zip(progress.as_ref())
+        .map(|(scenario, progress)| ScenarioResult::new(scenario, progress));
+    Report::new(&options, &world, result, errors)
+}
+
+/// A record of where everything was at every tick, written as one JSON object per line. The first
+/// line describes the map, with one character per cell: `#` for walls, `~` for water, `:` for
+/// mud, `=` for roads and the weed level for plain ground.
+struct Replay {
+    out: BufWriter<File>,
+}
+
+impl Replay {
+    fn create(path: &Path, world: &World) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let map: String = world
+            .shared
+            .map
+            .borrow()
+            .iter()
+            .map(|cell| match cell {
+                MapCell::Wall => '#',
+                MapCell::Water => '~',
+                MapCell::Mud => ':',
+                MapCell::Road => '=',
+                MapCell::Empty(weed) => char::from(b'0' + weed),
+            })
+            .collect();
+        let holes: Vec<_> = world
+            .shared
+            .holes
+            .borrow()
+            .iter()
+            .map(|hole| format!("[{}, {}]", hole.pos.x, hole.pos.y))
+            .collect();
+        writeln!(
+            out,
+            "{{\"board_size\": {BOARD_SIZE}, \"map\": \"{map}\", \"holes\": [{}]}}",
+            holes.join(", ")
+        )?;
+        Ok(Self { out })
+    }
+
+    fn record(&mut self, world: &World) -> std::io::Result<()> {
+        let agents: Vec<_> = world
+            .raccoons
+            .iter()
+            .map(|raccoon| {
+                let state = raccoon.state.borrow();
+                let kind = match raccoon.kind {
+                    AgentKind::Raccoon => "raccoon",
+                    AgentKind::Predator => "predator",
+                };
+                format!(
+                    "{{\"id\": {}, \"kind\": \"{kind}\", \"team\": {}, \"pos\": [{}, {}], \
+                    \"satiety\": {:.3}}}",
+                    raccoon.id, state.team, state.pos.x, state.pos.y, state.satiety
+                )
+            })
+            .collect();
+        let items: Vec<_> = world
+            .shared
+            .items
+            .borrow()
+            .iter()
+            .map(|item| format!("[{}, {}]", item.x, item.y))
+            .collect();
+        writeln!(
+            self.out,
+            "{{\"tick\": {}, \"agents\": [{}], \"items\": [{}]}}",
+            world.ticks(),
+            agents.join(", "),
+            items.join(", ")
+        )
+    }
+}
+
+/// The errors of all the reports, without repeating the ones that happened in many worlds.
+pub(crate) fn unique_errors(reports: &[Report]) -> Vec<&String> {
+    let mut errors: Vec<&String> = vec![];
+    for e in reports.iter().flat_map(|report| &report.errors) {
+        if !errors.contains(&e) {
+            errors.push(e);
+        }
+    }
+    errors
+}
+
+/// Run `job` on each of `jobs` on up to `threads` threads, or as many as there are CPUs, and
+/// return the results in the order of the jobs.
+///
+/// Each job has to build its own world, since worlds are made of `Rc`s that cannot be shared
+/// between threads.
+pub(crate) fn run_parallel<J: Sync, R: Send>(
+    jobs: &[J],
+    threads: Option<usize>,
+    job: impl Fn(&J) -> R + Sync,
+) -> Vec<R> {
+    let threads = threads
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .clamp(1, jobs.len().max(1));
+    let next_job = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..jobs.len()).map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let i = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(input) = jobs.get(i) else {
+                    break;
+                };
+                let result = job(input);
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+pub(crate) fn separator(i: usize, len: usize) -> &'static str {
+    if i + 1 < len {
+        ","
+    } else {
+        ""
+    }
+}
+
+/// Quote a string for JSON.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(json_string("plain"), r#""plain""#);
+        assert_eq!(
+            json_string("a \"b\"\\c\n\t\u{1}"),
+            r#""a \"b\"\\c\n\t\u0001""#
+        );
+    }
+
+    #[test]
+    fn small_report() {
+        let report = Report {
+            seed: Some(7),
+            scripts: vec!["a \"b\".rscl".to_string()],
+            ticks: 10,
+            population: 1,
+            births: 0,
+            deaths: 0,
+            raccoons: vec![RaccoonSummary {
+                id: 0,
+                team: 0,
+                generation: 0,
+                alive: true,
+                born: 0,
+                age: 10,
+                corn_eaten: 2,
+                collected: 3,
+                delivered: 1,
+                satiety_sum: 5.,
+                starving_ticks: 0,
+                script_errors: 1,
+                preempted: 0,
+                restarts: 1,
+            }],
+            scenario: Some(ScenarioResult {
+                name: "Stock up".to_string(),
+                objectives: vec![("Deliver 1 corn".to_string(), Status::Succeeded)],
+                outcome: Status::Succeeded,
+            }),
+            errors: vec!["x.rscl:1:2: bad \"token\"\n\tat \\".to_string()],
+        };
+        let expected = r#"{
+  "seed": 7,
+  "scripts": ["a \"b\".rscl"],
+  "ticks": 10,
+  "population": 1,
+  "births": 0,
+  "deaths": 0,
+  "corn_eaten": 2,
+  "delivered": 1,
+  "average_satiety": 0.5000,
+  "starvation_ticks": 0,
+  "script_errors": 1,
+  "restarts": 1,
+  "raccoons": [
+    {"id": 0, "team": 0, "generation": 0, "alive": true, "born": 0, "age": 10, "corn_eaten": 2, "collected": 3, "delivered": 1, "average_satiety": 0.5000, "starvation_ticks": 0, "script_errors": 1, "preempted": 0, "restarts": 1}
+  ],
+  "scenario": {"name": "Stock up", "outcome": "succeeded", "objectives": [{"objective": "Deliver 1 corn", "status": "succeeded"}]},
+  "errors": [
+    "x.rscl:1:2: bad \"token\"\n\tat \\"
+  ]
+}
+"#;
+        assert_eq!(report.to_json(), expected);
+    }
+}
@@ -0,0 +1,140 @@
+//! A tiny INI-like format for the data files that artists and level designers edit.
+//!
+//! ```text
+//! # Comments start with a hash
+//! [section]
+//! key = value
+//! ```
+//!
+//! Keys before the first section header belong to a section with an empty name.
+
+pub(crate) struct Section {
+    pub name: String,
+    /// Line number of the header, for error messages.
+    pub line: usize,
+    pub entries: Vec<Entry>,
+}
+
+pub(crate) struct Entry {
+    pub key: String,
+    pub value: String,
+    pub line: usize,
+}
+
+impl Section {
+    pub(crate) fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.key == key)
+    }
+
+    /// Like `get`, but a missing key is an error.
+    pub(crate) fn require(&self, key: &str) -> Result<&Entry, String> {
+        self.get(key)
+            .ok_or_else(|| format!("{}: [{}] is missing `{key}`", self.line, self.name))
+    }
+}
+
+impl Entry {
+    /// Parse the value, reporting the line on failure.
+    pub(crate) fn parse<T: std::str::FromStr>(&self) -> Result<T, String>
+    where
+        T::Err: std::fmt::Display,
+    {
+        self.value
+            .parse()
+            .map_err(|e| format!("{}: bad value for `{}`: {e}", self.line, self.key))
+    }
+}
+
+pub(crate) fn parse(src: &str) -> Result<Vec<Section>, String> {
+    let mut sections = vec![];
+    let mut current = Section {
+        name: String::new(),
+        line: 0,
+        entries: vec![],
+    };
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            let Some(name) = name.strip_suffix(']') else {
+                return Err(format!("{line_no}: unterminated section header"));
+            };
+            let next = Section {
+                name: name.trim().to_string(),
+                line: line_no,
+                entries: vec![],
+            };
+            let prev = std::mem::replace(&mut current, next);
+            if !prev.name.is_empty() || !prev.entries.is_empty() {
+                sections.push(prev);
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("{line_no}: expected `key = value`"));
+        };
+        current.entries.push(Entry {
+            key: key.trim().to_string(),
+            value: value.trim().to_string(),
+            line: line_no,
+        });
+    }
+    if !current.name.is_empty() || !current.entries.is_empty() {
+        sections.push(current);
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_and_entries() {
+        let src = "top = 1\n\n# A comment\n[first]\nname = Some value \n[ second ]\ncount=3\n";
+        let sections = parse(src).unwrap();
+        let names: Vec<_> = sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["", "first", "second"]);
+        assert_eq!(sections[0].get("top").unwrap().value, "1");
+        let name = sections[1].get("name").unwrap();
+        assert_eq!((name.value.as_str(), name.line), ("Some value", 5));
+        assert_eq!(sections[2].line, 6);
+        assert_eq!(
+            sections[2].require("count").unwrap().parse::<usize>(),
+            Ok(3)
+        );
+    }
+
+    #[test]
+    fn empty_sections_are_kept() {
+        let sections = parse("[empty]\n[full]\nkey = value\n").unwrap();
+        assert_eq!(sections.len(), 2);
+        assert!(sections[0].entries.is_empty());
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        assert_eq!(
+            parse("[ok]\n[broken\n").err().unwrap(),
+            "2: unterminated section header"
+        );
+        assert_eq!(
+            parse("[ok]\nno equals sign\n").err().unwrap(),
+            "2: expected `key = value`"
+        );
+        let sections = parse("[s]\nn = many\n").unwrap();
+        assert!(sections[0]
+            .get("n")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap_err()
+            .starts_with("2: "));
+        assert_eq!(
+            sections[0].require("missing").err().unwrap(),
+            "1: [s] is missing `missing`"
+        );
+    }
+}
@@ -1,20 +1,61 @@
+/// Print something that happened to an agent, unless event logging is turned off.
+macro_rules! log_event {
+    ($($arg:tt)*) => {
+        if $crate::raccoon::logging_events() {
+            println!($($arg)*);
+        }
+    };
+}
+
 mod app;
-mod bg_image;
+mod assets;
+mod cli;
+mod director;
+mod headless;
+mod ini;
+mod pathfinding;
 mod raccoon;
+mod scenario;
+mod sweep;
+mod tournament;
+mod world;
+
+use std::process::ExitCode;
 
-use app::{RuccoonApp, BOARD_SIZE, CELL_SIZE};
+use app::{RuccoonApp, STATS_WIDTH};
+use cli::{Command, Options, USAGE};
 use eframe::epaint::vec2;
+use world::{BOARD_SIZE, CELL_SIZE};
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if options.help {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    match options.command {
+        Command::Gui => {}
+        Command::Run => return headless::run(&options),
+        Command::Sweep => return sweep::run(&options),
+        Command::Tournament => return tournament::run(&options),
+    }
 
-fn main() {
     let mut native_options = eframe::NativeOptions::default();
     native_options.initial_window_size = Some(vec2(
-        (BOARD_SIZE * CELL_SIZE + 16) as f32,
+        (BOARD_SIZE * CELL_SIZE + 16) as f32 + STATS_WIDTH,
         (BOARD_SIZE * CELL_SIZE + 16) as f32,
     ));
     eframe::run_native(
         "ruccoon",
         native_options,
-        Box::new(|_cc| Box::new(RuccoonApp::new())),
+        Box::new(move |_cc| Box::new(RuccoonApp::new(&options))),
     )
     .unwrap();
+    ExitCode::SUCCESS
 }
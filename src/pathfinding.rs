@@ -0,0 +1,431 @@
+//! Path finding on the board, with interchangeable algorithms behind the [`Pathfinder`] trait.
+
+mod best_first;
+mod jps;
+
+use std::time::{Duration, Instant};
+
+use eframe::epaint::{pos2, Pos2, Vec2};
+
+use crate::world::{MapCell, BOARD_SIZE, BOARD_SIZE_I, CELL_SIZE_F};
+
+use self::{best_first::best_first_search, jps::jump_point_search};
+
+/// The four straight directions followed by the four diagonals.
+pub(crate) const DIRECTIONS: [Vec2; 8] = [
+    Vec2::new(-1., 0.),
+    Vec2::new(0., -1.),
+    Vec2::new(1., 0.),
+    Vec2::new(0., 1.),
+    Vec2::new(-1., -1.),
+    Vec2::new(1., -1.),
+    Vec2::new(1., 1.),
+    Vec2::new(-1., 1.),
+];
+
+/// Path costs are in tenths of a tick, so that diagonal steps can cost roughly √2 times as much
+/// as straight ones.
+pub(crate) const STRAIGHT_COST: i32 = 10;
+const DIAGONAL_COST: i32 = 14;
+
+/// Which neighbours an agent can step to, shared by all agents in the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Movement {
+    FourWay,
+    /// Diagonal steps are allowed as well, as long as they do not cut the corner of a wall.
+    EightWay,
+}
+
+impl Movement {
+    pub(crate) const ALL: [Self; 2] = [Self::FourWay, Self::EightWay];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::FourWay => "4 directions",
+            Self::EightWay => "8 directions",
+        }
+    }
+
+    pub(crate) fn directions(&self) -> &'static [Vec2] {
+        match self {
+            Self::FourWay => &DIRECTIONS[..4],
+            Self::EightWay => &DIRECTIONS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PathNode {
+    /// Index into `DIRECTIONS` to take from this node, or `None` at the goal.
+    pub(crate) direction: Option<u8>,
+    pub(crate) pos: [i32; 2],
+}
+
+impl From<&PathNode> for Pos2 {
+    fn from(value: &PathNode) -> Self {
+        pos2(
+            (value.pos[0] as f32 + 0.5) * CELL_SIZE_F,
+            (value.pos[1] as f32 + 0.5) * CELL_SIZE_F,
+        )
+    }
+}
+
+/// How much work a search took.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SearchStats {
+    pub(crate) expanded: usize,
+    pub(crate) elapsed: Duration,
+}
+
+impl SearchStats {
+    pub(crate) fn accumulate(&mut self, other: &SearchStats) {
+        self.expanded += other.expanded;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// A path in reverse order, so that popping from the back yields the next step. The first node is
+/// the goal.
+pub(crate) type Path = Vec<PathNode>;
+
+pub(crate) trait Pathfinder {
+    fn name(&self) -> &'static str;
+
+    /// Find the cheapest path from `start` to any of `goals`. Returns the number of expanded nodes
+    /// along with the path.
+    fn search(
+        &self,
+        start: [i32; 2],
+        map: &[MapCell],
+        goals: &[Pos2],
+        movement: Movement,
+    ) -> (Option<Path>, usize);
+}
+
+/// Uniform cost search, which finds the nearest of many goals.
+pub(crate) struct Dijkstra;
+
+impl Pathfinder for Dijkstra {
+    fn name(&self) -> &'static str {
+        "Dijkstra"
+    }
+
+    fn search(
+        &self,
+        start: [i32; 2],
+        map: &[MapCell],
+        goals: &[Pos2],
+        movement: Movement,
+    ) -> (Option<Path>, usize) {
+        best_first_search(start, map, goals, movement, |_| 0)
+    }
+}
+
+/// A* with the distance to the closest goal on an empty board as the heuristic, which is the
+/// Manhattan distance for 4-way movement and the octile distance for 8-way movement. Every
+/// cell costs at least 1 to enter, so the heuristic never overestimates.
+pub(crate) struct AStar;
+
+impl Pathfinder for AStar {
+    fn name(&self) -> &'static str {
+        "A*"
+    }
+
+    fn search(
+        &self,
+        start: [i32; 2],
+        map: &[MapCell],
+        goals: &[Pos2],
+        movement: Movement,
+    ) -> (Option<Path>, usize) {
+        let goals_i: Vec<_> = goals.iter().map(|goal| to_cell(*goal)).collect();
+        best_first_search(start, map, goals, movement, |pos| {
+            goals_i
+                .iter()
+                .map(|goal| {
+                    let dx = (goal[0] - pos[0]).abs();
+                    let dy = (goal[1] - pos[1]).abs();
+                    match movement {
+                        Movement::FourWay => STRAIGHT_COST * (dx + dy),
+                        Movement::EightWay => {
+                            STRAIGHT_COST * (dx + dy)
+                                - (2 * STRAIGHT_COST - DIAGONAL_COST) * dx.min(dy)
+                        }
+                    }
+                })
+                .min()
+                .unwrap_or(0)
+        })
+    }
+}
+
+/// Jump Point Search, which skips over runs of open cells and pays off on large open maps.
+/// Its pruning assumes that every cell costs the same to enter, so on maps with mud or weeds, as
+/// well as with 8-way movement, which it does not support, it falls back to A*.
+pub(crate) struct JumpPointSearch;
+
+impl Pathfinder for JumpPointSearch {
+    fn name(&self) -> &'static str {
+        "Jump Point Search"
+    }
+
+    fn search(
+        &self,
+        start: [i32; 2],
+        map: &[MapCell],
+        goals: &[Pos2],
+        movement: Movement,
+    ) -> (Option<Path>, usize) {
+        if movement == Movement::FourWay && is_uniform(map) {
+            jump_point_search(start, map, goals)
+        } else {
+            AStar.search(start, map, goals, movement)
+        }
+    }
+}
+
+/// The algorithm selection shared by all agents in the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    /// A* for a single goal and Dijkstra for many.
+    Auto,
+    Dijkstra,
+    AStar,
+    JumpPoint,
+}
+
+impl Algorithm {
+    pub(crate) const ALL: [Self; 4] = [Self::Auto, Self::Dijkstra, Self::AStar, Self::JumpPoint];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Auto => "Auto",
+            _ => self.pathfinder(2).name(),
+        }
+    }
+
+    fn pathfinder(&self, num_goals: usize) -> &'static dyn Pathfinder {
+        match self {
+            Self::Auto if num_goals == 1 => &AStar,
+            Self::Auto | Self::Dijkstra => &Dijkstra,
+            Self::AStar => &AStar,
+            Self::JumpPoint => &JumpPointSearch,
+        }
+    }
+}
+
+/// Find a path with the given algorithm and measure how long it took.
+pub(crate) fn find_path(
+    algorithm: Algorithm,
+    movement: Movement,
+    start: [i32; 2],
+    map: &[MapCell],
+    goals: &[Pos2],
+) -> (Option<Path>, SearchStats) {
+    let time = Instant::now();
+    let (path, expanded) = algorithm
+        .pathfinder(goals.len())
+        .search(start, map, goals, movement);
+    let stats = SearchStats {
+        expanded,
+        elapsed: time.elapsed(),
+    };
+    (path, stats)
+}
+
+/// Whether every passable cell of `map` costs the same to enter.
+fn is_uniform(map: &[MapCell]) -> bool {
+    let mut costs = map.iter().filter_map(MapCell::move_cost);
+    let first = costs.next();
+    costs.all(|cost| Some(cost) == first)
+}
+
+fn to_cell(pos: Pos2) -> [i32; 2] {
+    [pos.x as i32, pos.y as i32]
+}
+
+fn in_bounds(pos: [i32; 2]) -> bool {
+    0 <= pos[0] && pos[0] < BOARD_SIZE_I && 0 <= pos[1] && pos[1] < BOARD_SIZE_I
+}
+
+fn cell_index(pos: [i32; 2]) -> usize {
+    (pos[0] + pos[1] * BOARD_SIZE_I) as usize
+}
+
+/// The cost of stepping into `pos`, or `None` if it is out of the board or impassable.
+fn move_cost(map: &[MapCell], pos: [i32; 2]) -> Option<i32> {
+    if !in_bounds(pos) {
+        return None;
+    }
+    map[cell_index(pos)].move_cost().map(|cost| cost as i32)
+}
+
+/// Whether an agent at `from` can step towards `direction`. Diagonal steps need both of the
+/// cells they pass by to be passable, so agents cannot squeeze through the corners of walls.
+pub(crate) fn can_step(map: &[MapCell], from: [i32; 2], direction: Vec2) -> bool {
+    let [dx, dy] = [direction.x as i32, direction.y as i32];
+    let to = [from[0] + dx, from[1] + dy];
+    if move_cost(map, to).is_none() {
+        return false;
+    }
+    dx == 0
+        || dy == 0
+        || move_cost(map, [from[0] + dx, from[1]]).is_some()
+            && move_cost(map, [from[0], from[1] + dy]).is_some()
+}
+
+/// The path cost of stepping from `from` towards `direction`, or `None` if the step is not
+/// allowed.
+pub(crate) fn step_cost(map: &[MapCell], from: [i32; 2], direction: Vec2) -> Option<i32> {
+    if !can_step(map, from, direction) {
+        return None;
+    }
+    let to = [from[0] + direction.x as i32, from[1] + direction.y as i32];
+    let scale = if direction.x != 0. && direction.y != 0. {
+        DIAGONAL_COST
+    } else {
+        STRAIGHT_COST
+    };
+    move_cost(map, to).map(|cost| cost * scale)
+}
+
+/// Build a path from the cells visited in order from the start to the goal. Consecutive cells
+/// may be apart in a straight line, in which case the cells in between are filled in.
+fn build_path(waypoints: &[[i32; 2]]) -> Path {
+    let mut cells = vec![];
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        let Some(next) = waypoints.get(i + 1) else {
+            cells.push(*waypoint);
+            break;
+        };
+        let step = [
+            (next[0] - waypoint[0]).signum(),
+            (next[1] - waypoint[1]).signum(),
+        ];
+        let mut cur = *waypoint;
+        while cur != *next {
+            cells.push(cur);
+            cur = [cur[0] + step[0], cur[1] + step[1]];
+        }
+    }
+
+    let mut path = Vec::with_capacity(cells.len());
+    let mut next = None;
+    for cell in cells.iter().rev() {
+        let direction = next.and_then(|next: [i32; 2]| {
+            DIRECTIONS
+                .iter()
+                .position(|d| [cell[0] + d.x as i32, cell[1] + d.y as i32] == next)
+                .map(|i| i as u8)
+        });
+        path.push(PathNode {
+            direction,
+            pos: *cell,
+        });
+        next = Some(*cell);
+    }
+    path
+}
+
+type CameFrom = [Option<[i32; 2]>; BOARD_SIZE * BOARD_SIZE];
+
+/// Walk `came_from` back from the goal and build the path.
+fn trace_back(came_from: &CameFrom, goal: [i32; 2]) -> Path {
+    let mut waypoints = vec![goal];
+    let mut cur = goal;
+    while let Some(prev) = came_from[cell_index(cur)] {
+        waypoints.push(prev);
+        cur = prev;
+    }
+    waypoints.reverse();
+    build_path(&waypoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map with walls, water, mud and weeds of every height.
+    fn weighted_map() -> Vec<MapCell> {
+        let mut map = vec![MapCell::Empty(0); BOARD_SIZE * BOARD_SIZE];
+        for (i, cell) in map.iter_mut().enumerate() {
+            let [x, y] = [i % BOARD_SIZE, i / BOARD_SIZE];
+            *cell = if (x * 7 + y * 3) % 11 == 0 {
+                MapCell::Wall
+            } else if (x * 5 + y) % 13 == 0 {
+                MapCell::Water
+            } else if (x + y * 5) % 7 == 0 {
+                MapCell::Mud
+            } else {
+                MapCell::Empty(((x * y) % 7) as u8)
+            };
+        }
+        map
+    }
+
+    /// The same map with the weeds and mud cleared away.
+    fn uniform_map() -> Vec<MapCell> {
+        weighted_map()
+            .into_iter()
+            .map(|cell| match cell {
+                MapCell::Mud | MapCell::Empty(_) => MapCell::Empty(0),
+                _ => cell,
+            })
+            .collect()
+    }
+
+    /// The cost of walking a path from its start to its goal.
+    fn path_cost(map: &[MapCell], path: &Path) -> i32 {
+        path.windows(2)
+            .map(|pair| {
+                let [to, from] = [pair[0].pos, pair[1].pos];
+                let direction = Vec2::new((to[0] - from[0]) as f32, (to[1] - from[1]) as f32);
+                step_cost(map, from, direction).expect("the path takes a step that is not allowed")
+            })
+            .sum()
+    }
+
+    fn passable_cells(map: &[MapCell]) -> Vec<[i32; 2]> {
+        (0..BOARD_SIZE_I * BOARD_SIZE_I)
+            .map(|i| [i % BOARD_SIZE_I, i / BOARD_SIZE_I])
+            .filter(|pos| move_cost(map, *pos).is_some())
+            .collect()
+    }
+
+    /// Every algorithm finds a path to the same goals at the same cost.
+    fn assert_same_costs(map: &[MapCell], movement: Movement) {
+        let cells = passable_cells(map);
+        let algorithms: [&dyn Pathfinder; 3] = [&Dijkstra, &AStar, &JumpPointSearch];
+        for start in cells.iter().step_by(7) {
+            for goal in cells.iter().step_by(5) {
+                let goals = [pos2(goal[0] as f32, goal[1] as f32)];
+                let costs: Vec<_> = algorithms
+                    .iter()
+                    .map(|algorithm| {
+                        let (path, _) = algorithm.search(*start, map, &goals, movement);
+                        path.map(|path| path_cost(map, &path))
+                    })
+                    .collect();
+                assert!(
+                    costs.iter().all(|cost| *cost == costs[0]),
+                    "{movement:?} from {start:?} to {goal:?}: {costs:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn algorithms_agree_on_weighted_map() {
+        for movement in Movement::ALL {
+            assert_same_costs(&weighted_map(), movement);
+        }
+    }
+
+    #[test]
+    fn algorithms_agree_on_uniform_map() {
+        assert!(is_uniform(&uniform_map()));
+        for movement in Movement::ALL {
+            assert_same_costs(&uniform_map(), movement);
+        }
+    }
+}
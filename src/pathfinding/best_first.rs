@@ -0,0 +1,88 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use eframe::epaint::Pos2;
+
+use super::{cell_index, step_cost, to_cell, trace_back, CameFrom, Movement, Path};
+use crate::world::{MapCell, BOARD_SIZE};
+
+#[derive(Eq)]
+pub(super) struct MinCost {
+    pub(super) pos: [i32; 2],
+    pub(super) cost: i32,
+    /// Cost plus heuristic, which decides the order of expansion.
+    pub(super) priority: i32,
+}
+
+impl PartialEq for MinCost {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority.eq(&other.priority)
+    }
+}
+
+impl PartialOrd for MinCost {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// BinaryHeap is a max-heap and only looks at Ord, so it has to be reversed here rather than only
+// in PartialOrd.
+impl Ord for MinCost {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        Reverse(self.priority).cmp(&Reverse(other.priority))
+    }
+}
+
+/// Best-first search shared by Dijkstra (zero heuristic) and A*. Stops as soon as any goal is
+/// expanded, which is the cheapest one as long as the heuristic is admissible.
+pub(super) fn best_first_search(
+    start: [i32; 2],
+    map: &[MapCell],
+    goals: &[Pos2],
+    movement: Movement,
+    heuristic: impl Fn([i32; 2]) -> i32,
+) -> (Option<Path>, usize) {
+    let goals: Vec<_> = goals.iter().map(|goal| to_cell(*goal)).collect();
+    let mut cost_map = [i32::MAX; BOARD_SIZE * BOARD_SIZE];
+    let mut came_from: CameFrom = [None; BOARD_SIZE * BOARD_SIZE];
+    let mut expanded = 0;
+
+    let mut open_set = BinaryHeap::new();
+    open_set.push(MinCost {
+        pos: start,
+        cost: 0,
+        priority: heuristic(start),
+    });
+    cost_map[cell_index(start)] = 0;
+    while let Some(state) = open_set.pop() {
+        if cost_map[cell_index(state.pos)] < state.cost {
+            // A cheaper route to this cell has already been expanded.
+            continue;
+        }
+        if goals.contains(&state.pos) {
+            return (Some(trace_back(&came_from, state.pos)), expanded);
+        }
+        expanded += 1;
+        for direction in movement.directions() {
+            let next = [
+                state.pos[0] + direction.x as i32,
+                state.pos[1] + direction.y as i32,
+            ];
+            let Some(move_cost) = step_cost(map, state.pos, *direction) else {
+                continue;
+            };
+            let cost = state.cost + move_cost;
+            let idx = cell_index(next);
+            if cost < cost_map[idx] {
+                open_set.push(MinCost {
+                    pos: next,
+                    cost,
+                    priority: cost + heuristic(next),
+                });
+                cost_map[idx] = cost;
+                came_from[idx] = Some(state.pos);
+            }
+        }
+    }
+    (None, expanded)
+}
@@ -0,0 +1,148 @@
+use std::collections::BinaryHeap;
+
+use eframe::epaint::Pos2;
+
+use super::{
+    best_first::MinCost, cell_index, move_cost, to_cell, trace_back, CameFrom, Path, DIRECTIONS,
+};
+use crate::world::{MapCell, BOARD_SIZE};
+
+const UP: [i32; 2] = [0, -1];
+const DOWN: [i32; 2] = [0, 1];
+const LEFT: [i32; 2] = [-1, 0];
+const RIGHT: [i32; 2] = [1, 0];
+
+fn add(a: [i32; 2], b: [i32; 2]) -> [i32; 2] {
+    [a[0] + b[0], a[1] + b[1]]
+}
+
+/// Jump Point Search on a 4-connected grid. Paths are canonical in that they go horizontally
+/// first, so horizontal jumps probe vertically at every cell, while vertical jumps only stop at
+/// forced neighbours.
+pub(super) fn jump_point_search(
+    start: [i32; 2],
+    map: &[MapCell],
+    goals: &[Pos2],
+) -> (Option<Path>, usize) {
+    let goals: Vec<_> = goals.iter().map(|goal| to_cell(*goal)).collect();
+    let heuristic = |pos: [i32; 2]| {
+        goals
+            .iter()
+            .map(|goal| (goal[0] - pos[0]).abs() + (goal[1] - pos[1]).abs())
+            .min()
+            .unwrap_or(0)
+    };
+    let mut cost_map = [i32::MAX; BOARD_SIZE * BOARD_SIZE];
+    let mut came_from: CameFrom = [None; BOARD_SIZE * BOARD_SIZE];
+    let mut expanded = 0;
+
+    let mut open_set = BinaryHeap::new();
+    open_set.push(MinCost {
+        pos: start,
+        cost: 0,
+        priority: heuristic(start),
+    });
+    cost_map[cell_index(start)] = 0;
+    while let Some(state) = open_set.pop() {
+        let idx = cell_index(state.pos);
+        if cost_map[idx] < state.cost {
+            continue;
+        }
+        if goals.contains(&state.pos) {
+            return (Some(trace_back(&came_from, state.pos)), expanded);
+        }
+        expanded += 1;
+        let parent_dir = came_from[idx].map(|prev| {
+            [
+                (state.pos[0] - prev[0]).signum(),
+                (state.pos[1] - prev[1]).signum(),
+            ]
+        });
+        for dir in successors(map, state.pos, parent_dir) {
+            let Some((jump_point, jump_cost)) = jump(map, &goals, state.pos, dir) else {
+                continue;
+            };
+            let cost = state.cost + jump_cost;
+            let jump_idx = cell_index(jump_point);
+            if cost < cost_map[jump_idx] {
+                open_set.push(MinCost {
+                    pos: jump_point,
+                    cost,
+                    priority: cost + heuristic(jump_point),
+                });
+                cost_map[jump_idx] = cost;
+                came_from[jump_idx] = Some(state.pos);
+            }
+        }
+    }
+    (None, expanded)
+}
+
+/// Directions worth exploring from `pos` after arriving in `parent_dir`.
+fn successors(map: &[MapCell], pos: [i32; 2], parent_dir: Option<[i32; 2]>) -> Vec<[i32; 2]> {
+    let Some(dir) = parent_dir else {
+        return DIRECTIONS[..4]
+            .iter()
+            .map(|d| [d.x as i32, d.y as i32])
+            .collect();
+    };
+    if dir[0] != 0 {
+        return vec![dir, UP, DOWN];
+    }
+    let prev = [pos[0] - dir[0], pos[1] - dir[1]];
+    let mut ret = vec![dir];
+    ret.extend(
+        [LEFT, RIGHT]
+            .into_iter()
+            .filter(|side| is_forced(map, prev, pos, *side)),
+    );
+    ret
+}
+
+/// Whether the neighbour of `node` towards `side` cannot be reached at least as cheaply by going
+/// sideways from `prev` first.
+fn is_forced(map: &[MapCell], prev: [i32; 2], node: [i32; 2], side: [i32; 2]) -> bool {
+    if move_cost(map, add(node, side)).is_none() {
+        return false;
+    }
+    match (move_cost(map, add(prev, side)), move_cost(map, node)) {
+        (Some(detour), Some(here)) => here < detour,
+        _ => true,
+    }
+}
+
+/// Move from `from` towards `dir` until something interesting happens. Returns the jump point
+/// and the cost to get there, or `None` if we hit a wall or the edge of the board.
+fn jump(
+    map: &[MapCell],
+    goals: &[[i32; 2]],
+    from: [i32; 2],
+    dir: [i32; 2],
+) -> Option<([i32; 2], i32)> {
+    let mut cur = from;
+    let mut cost = 0;
+    loop {
+        let next = add(cur, dir);
+        let next_cost = move_cost(map, next)?;
+        cost += next_cost;
+        if goals.contains(&next) {
+            return Some((next, cost));
+        }
+        // Runs of the same terrain can be skipped over, but a change in terrain can change the
+        // best path.
+        if move_cost(map, cur) != Some(next_cost) {
+            return Some((next, cost));
+        }
+        if dir[0] != 0 {
+            if jump(map, goals, next, UP).is_some() || jump(map, goals, next, DOWN).is_some() {
+                return Some((next, cost));
+            }
+        } else if [LEFT, RIGHT]
+            .into_iter()
+            .any(|side| is_forced(map, cur, next, side))
+        {
+            return Some((next, cost));
+        }
+        cur = next;
+    }
+}
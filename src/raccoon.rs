@@ -1,9 +1,21 @@
+mod action;
+mod event;
+mod preempt;
 mod render;
+mod task;
+mod traffic;
 
-use std::{cell::RefCell, cmp::Reverse, collections::BinaryHeap, error::Error, rc::Rc};
+use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    error::Error,
+    rc::Rc,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
-use eframe::epaint::{pos2, Color32, Pos2, Vec2};
-use rand::{rngs::ThreadRng, Rng};
+use eframe::epaint::{pos2, Color32, Pos2};
+use rand::{rngs::StdRng, Rng};
 use ruscal::{
     ast::TypeDecl,
     bytecode::{ByteCode, NativeFn},
@@ -12,201 +24,943 @@ use ruscal::{
     type_checker::{type_check, TypeCheckContext},
     value::Value,
     vm::{Vm, YieldResult},
-    Args,
 };
 
-use crate::app::{Hole, MapCell, BOARD_SIZE, BOARD_SIZE_I, CELL_SIZE_F};
+pub(crate) use self::action::Action;
+use self::action::{move_code, ActionResult, ACTION_CONSTANTS, ACTION_WAIT, RESULT_CONSTANTS};
+use self::event::{notify, Event, HUNGRY_SATIETY};
+pub(crate) use self::preempt::{Location, CHECKPOINT_FN};
+use self::task::{insertion_index, Task, TaskRequest, MAIN_TASK, MAX_LOGGED_ERRORS, MAX_TASKS};
+pub(crate) use self::task::{RestartPolicy, ScriptError};
+pub(crate) use self::traffic::{
+    reserve_all, resolve_moves, Reservations, TrafficRules, STUCK_TICKS,
+};
+use crate::{
+    pathfinding::{self, Path, PathNode, SearchStats, DIRECTIONS},
+    world::{AgentInfo, Hole, MapCell, Message, SharedWorld, BOARD_SIZE},
+};
 
-const DIRECTIONS: [Vec2; 4] = [
-    Vec2::new(-1., 0.),
-    Vec2::new(0., -1.),
-    Vec2::new(1., 0.),
-    Vec2::new(0., 1.),
-];
+const CARRY_CAPACITY: usize = 2;
 
-const CORN_ENERGY: f32 = 0.2;
-const HUNGER_RATE: f32 = 0.005;
+/// Whether to print the events of the agents' lives, such as eating and dying. Nobody reads them
+/// when many worlds are simulated at once.
+static LOG_EVENTS: AtomicBool = AtomicBool::new(true);
 
-pub(crate) struct Raccoon {
-    id: usize,
-    pub(crate) state: Rc<RefCell<RaccoonState>>,
-    vm: Rc<RefCell<Vm>>,
+pub(crate) fn set_logging_events(on: bool) {
+    LOG_EVENTS.store(on, Ordering::Relaxed);
 }
 
-#[derive(Debug, Clone, Copy)]
-pub(crate) struct PathNode {
-    direction: u8,
-    pos: [i32; 2],
+pub(crate) fn logging_events() -> bool {
+    LOG_EVENTS.load(Ordering::Relaxed)
 }
 
-impl From<&PathNode> for Pos2 {
-    fn from(value: &PathNode) -> Self {
-        pos2(
-            (value.pos[0] as f32 + 0.5) * CELL_SIZE_F,
-            (value.pos[1] as f32 + 0.5) * CELL_SIZE_F,
-        )
+/// Default number of steps a script can take in a single tick before it is pre-empted. A step is
+/// a native function call, or a pass through the start of a loop body or a function, see
+/// `preempt`. The shipped script only needs a handful per tick.
+pub(crate) const DEFAULT_STEP_BUDGET: usize = 1000;
+
+/// Parameters of the raccoons' life cycle, shared by the whole colony.
+#[derive(Debug, Clone)]
+pub(crate) struct LifeCycle {
+    /// Number of raccoons the world starts with.
+    pub initial_population: usize,
+    /// Satiety lost every tick.
+    pub hunger_rate: f32,
+    /// Satiety gained by eating a corn.
+    pub corn_energy: f32,
+    /// Ticks a raccoon survives at zero satiety before it starves to death.
+    pub starvation_ticks: usize,
+    /// Age in ticks at which a raccoon dies of old age.
+    pub max_age: usize,
+    /// Age in ticks from which a raccoon can reproduce.
+    pub maturity: usize,
+    /// Satiety a raccoon needs to give birth in a hole.
+    pub birth_satiety: f32,
+    /// Satiety the parent passes on to the newborn.
+    pub birth_cost: f32,
+    /// Ticks a parent has to wait between births.
+    pub birth_interval: usize,
+    pub max_population: usize,
+}
+
+impl Default for LifeCycle {
+    fn default() -> Self {
+        Self {
+            initial_population: 2,
+            hunger_rate: 0.005,
+            corn_energy: 0.2,
+            starvation_ticks: 100,
+            max_age: 5000,
+            maturity: 300,
+            birth_satiety: 0.9,
+            birth_cost: 0.4,
+            birth_interval: 500,
+            max_population: 10,
+        }
     }
 }
 
+impl LifeCycle {
+    /// Names of the parameters that can be set with `set`.
+    pub(crate) const PARAMS: [&'static str; 10] = [
+        "raccoons",
+        "hunger_rate",
+        "corn_energy",
+        "starvation_ticks",
+        "max_age",
+        "maturity",
+        "birth_satiety",
+        "birth_cost",
+        "birth_interval",
+        "max_population",
+    ];
+
+    /// Set a parameter by its name in `PARAMS`. Counts are rounded to the nearest integer.
+    pub(crate) fn set(&mut self, name: &str, value: f64) -> Result<(), String> {
+        let count = value.round().max(0.) as usize;
+        match name {
+            "raccoons" => self.initial_population = count,
+            "hunger_rate" => self.hunger_rate = value as f32,
+            "corn_energy" => self.corn_energy = value as f32,
+            "starvation_ticks" => self.starvation_ticks = count,
+            "max_age" => self.max_age = count,
+            "maturity" => self.maturity = count,
+            "birth_satiety" => self.birth_satiety = value as f32,
+            "birth_cost" => self.birth_cost = value as f32,
+            "birth_interval" => self.birth_interval = count,
+            "max_population" => self.max_population = count,
+            _ => {
+                return Err(format!(
+                    "unknown parameter {name}, expected one of {}",
+                    Self::PARAMS.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The kind of an agent, which decides the rules it lives by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AgentKind {
+    /// Forages for corn and hides in holes.
+    Raccoon,
+    /// Hunts raccoons that are outside holes. It never gets hungry.
+    Predator,
+}
+
+pub(crate) struct Raccoon {
+    pub(crate) id: usize,
+    pub(crate) kind: AgentKind,
+    pub(crate) state: Rc<RefCell<RaccoonState>>,
+    /// The coroutines of the script, each on a VM of its own, in the order they are scheduled.
+    tasks: RefCell<Vec<Task>>,
+    /// Another VM on the same bytecode that runs the script's event handlers, so that the tasks
+    /// stay suspended meanwhile.
+    handler_vm: RefCell<Vm>,
+    /// The handler that yielded and has to be resumed before anything else, if any.
+    handling: Cell<Option<&'static str>>,
+    /// The checkpoint the handler passed last.
+    handler_location: Cell<Option<Location>>,
+    bytecode: Rc<ByteCode>,
+    debug_output: bool,
+}
+
 pub(crate) struct RaccoonState {
     pub(crate) pos: Pos2,
+    /// Position at the start of the current move, or of the tick if the agent is not moving, to
+    /// interpolate the sprite from.
+    pub(crate) prev_pos: Pos2,
+    /// Whether the sprite is flipped to face left.
+    pub(crate) facing_left: bool,
+    /// Number of steps taken, which picks the frame of the walk cycle.
+    pub(crate) walk_step: usize,
+    /// Walking time in path cost units that did not add up to a whole tick yet, carried over to
+    /// the next step so that diagonal steps take √2 times as long on average.
+    walk_carry: u32,
     pub(crate) tint: Color32,
     pub(crate) path: Option<Vec<PathNode>>,
+    /// Work done by the last path search.
+    pub(crate) last_search: SearchStats,
+    /// Work done by all path searches so far.
+    pub(crate) total_search: SearchStats,
     pub(crate) ate: usize,
+    /// Number of corns taken from the ground, either eaten or picked up.
+    pub(crate) collected: usize,
     pub(crate) satiety: f32,
+    pub(crate) last_action: ActionResult,
+    /// Remaining ticks until the current action finishes.
+    pub(crate) busy: u32,
+    /// Ticks the last move takes in total, over which the sprite slides to the new cell.
+    pub(crate) move_ticks: u32,
+    pub(crate) carrying: usize,
+    /// Number of corns brought back and stored in holes.
+    pub(crate) delivered: usize,
+    pub(crate) in_hole: bool,
+    /// Steps the script is allowed to take per tick.
+    pub(crate) step_budget: usize,
+    /// Steps the script has taken since the last tick started.
+    pub(crate) steps: usize,
+    /// Whether the script is yielding from a checkpoint because it ran out of steps, rather than
+    /// to act.
+    preempting: bool,
+    /// The checkpoint the running coroutine passed last, where its errors are reported.
+    checkpoint: Option<Location>,
+    /// Whether the script has been pre-empted in the last tick.
+    pub(crate) misbehaving: bool,
+    /// Total number of times the script has been pre-empted.
+    pub(crate) preempted: usize,
+    pub(crate) age: usize,
+    /// Ticks spent at zero satiety in a row.
+    pub(crate) starving: usize,
+    /// Ticks until the raccoon can give birth again.
+    pub(crate) birth_cooldown: usize,
+    pub(crate) generation: usize,
+    pub(crate) dead: bool,
+    /// Number of raccoons this predator has caught.
+    pub(crate) caught: usize,
+    /// The cell the agent decided to step into in this tick, settled by `resolve_moves`.
+    planned_move: Option<Pos2>,
+    /// Ticks in a row the agent has been blocked by other agents.
+    pub(crate) stuck: usize,
+    /// Satiety summed over every tick lived, for the average.
+    pub(crate) satiety_sum: f64,
+    /// Ticks spent at zero satiety in total.
+    pub(crate) starving_total: usize,
+    /// Number of times the script failed with an error.
+    pub(crate) script_errors: usize,
+    /// The latest errors of the script, oldest first, up to `MAX_LOGGED_ERRORS`.
+    pub(crate) error_log: VecDeque<ScriptError>,
+    /// What happens when `main` runs to its end or fails.
+    pub(crate) restart_policy: RestartPolicy,
+    /// Number of times `main` was started over after it ended or failed.
+    pub(crate) restarts: usize,
+    /// Whether the script was stopped for good by `RestartPolicy::Halt`.
+    pub(crate) halted: bool,
+    /// The script the agent's lineage runs, as its index in the list of scripts of the world.
+    pub(crate) team: usize,
+    /// Tick the agent was born in.
+    pub(crate) born: usize,
+    /// What killed the agent, once it is dead.
+    pub(crate) cause_of_death: Option<&'static str>,
+    /// Events waiting for the script's handlers.
+    pub(crate) events: VecDeque<Event>,
+    /// Whether satiety was below `HUNGRY_SATIETY` at the end of the last tick.
+    hungry: bool,
+    /// Tasks the script asked to start or stop since the scheduler last ran.
+    task_requests: Vec<TaskRequest>,
+    /// Number of tasks running, including `main`.
+    pub(crate) task_count: usize,
+    next_task_id: usize,
+}
+
+/// How a coroutine of a script came back to the host.
+enum Resumed {
+    /// It yielded, with an action code if the value was one.
+    Yielded(Option<i64>),
+    /// It ran to its end.
+    Finished,
+    /// It failed with an error, which is in the raccoon's error log.
+    Failed,
+    /// It ran out of its step budget and yielded at a checkpoint, to carry on in the next turn.
+    Preempted,
 }
 
 struct VmUserData {
+    id: usize,
     state: Rc<RefCell<RaccoonState>>,
-    map: Rc<Vec<MapCell>>,
-    items: Rc<RefCell<Vec<Pos2>>>,
-    holes: Rc<Vec<Hole>>,
+    world: SharedWorld,
 }
 
 impl Raccoon {
     pub(crate) fn new(
         id: usize,
-        map: &Rc<Vec<MapCell>>,
-        items: &Rc<RefCell<Vec<Pos2>>>,
-        holes: &Rc<Vec<Hole>>,
+        kind: AgentKind,
+        world: &SharedWorld,
         bytecode: &Rc<ByteCode>,
+        step_budget: usize,
         debug_output: bool,
     ) -> Self {
-        let mut rng = rand::thread_rng();
-        let gen_channel = |rng: &mut ThreadRng| rng.gen::<u8>() / 2 + 127;
+        let mut rng = world.rng.borrow_mut();
+        let gen_channel = |rng: &mut StdRng| rng.gen::<u8>() / 2 + 127;
+        let pos = pos2(
+            rng.gen_range(0..BOARD_SIZE) as f32,
+            rng.gen_range(0..BOARD_SIZE) as f32,
+        );
         let state = Rc::new(RefCell::new(RaccoonState {
-            pos: pos2(
-                rng.gen_range(0..BOARD_SIZE) as f32,
-                rng.gen_range(0..BOARD_SIZE) as f32,
-            ),
+            pos,
+            prev_pos: pos,
+            facing_left: false,
+            walk_step: 0,
+            walk_carry: 0,
             tint: Color32::from_rgb(
                 gen_channel(&mut rng),
                 gen_channel(&mut rng),
                 gen_channel(&mut rng),
             ),
             path: None,
+            last_search: SearchStats::default(),
+            total_search: SearchStats::default(),
             ate: 0,
+            collected: 0,
             satiety: 0.5,
+            last_action: ActionResult::None,
+            busy: 0,
+            move_ticks: 1,
+            carrying: 0,
+            delivered: 0,
+            in_hole: false,
+            step_budget,
+            steps: 0,
+            preempting: false,
+            checkpoint: None,
+            misbehaving: false,
+            preempted: 0,
+            age: 0,
+            starving: 0,
+            birth_cooldown: 0,
+            generation: 0,
+            dead: false,
+            caught: 0,
+            planned_move: None,
+            stuck: 0,
+            satiety_sum: 0.,
+            starving_total: 0,
+            script_errors: 0,
+            error_log: VecDeque::new(),
+            restart_policy: RestartPolicy::default(),
+            restarts: 0,
+            halted: false,
+            team: 0,
+            born: 0,
+            cause_of_death: None,
+            events: VecDeque::new(),
+            hungry: false,
+            task_requests: vec![],
+            task_count: 1,
+            next_task_id: MAIN_TASK + 1,
         }));
+        drop(rng);
 
+        let new_vm = || script_vm(id, &state, world, bytecode, debug_output);
+        let main = Task {
+            id: MAIN_TASK,
+            function: "main".to_string(),
+            priority: 0,
+            vm: new_vm(),
+            location: None,
+        };
         Self {
             id,
+            kind,
             state: state.clone(),
-            vm: Rc::new(RefCell::new(Vm::new(
-                bytecode.clone(),
-                Box::new(VmUserData {
-                    state,
-                    map: map.clone(),
-                    items: items.clone(),
-                    holes: holes.clone(),
-                }),
-                debug_output,
-            ))),
+            tasks: RefCell::new(vec![main]),
+            handler_vm: RefCell::new(new_vm()),
+            handling: Cell::new(None),
+            handler_location: Cell::new(None),
+            bytecode: bytecode.clone(),
+            debug_output,
         }
     }
 
-    pub(crate) fn animate(
-        &self,
-        others: &[Raccoon],
-        map: &Rc<Vec<MapCell>>,
-        items: &Rc<RefCell<Vec<Pos2>>>,
-        holes: &Rc<Vec<Hole>>,
-    ) {
-        let mut vm = self.vm.borrow_mut();
-        if vm.top().is_err() {
-            if let Err(e) = vm.init_fn("main", &[]) {
-                eprintln!("Error in raccoon {}: init_fn: {e}", self.id);
-            }
+    pub(crate) fn info(&self) -> AgentInfo {
+        let state = self.state.borrow();
+        AgentInfo {
+            id: self.id,
+            kind: self.kind,
+            team: state.team,
+            pos: state.pos,
+            in_hole: state.in_hole,
         }
+    }
 
-        let direction_code = match vm.interpret() {
-            Ok(YieldResult::Finished(_)) => None,
-            Ok(YieldResult::Suspend(res)) => res.coerce_i64().ok(),
-            Err(e) => {
-                eprintln!("Error in raccoon {}: {e}", self.id);
-                None
+    pub(crate) fn animate(&self, world: &SharedWorld, life: &LifeCycle) {
+        let busy = {
+            let mut state = self.state.borrow_mut();
+            // A move on rough terrain keeps its start cell until it is done.
+            if state.busy == 0 {
+                state.prev_pos = state.pos;
+            }
+            if state.dead {
+                // Caught earlier in this tick
+                return;
+            }
+            if 0 < state.busy {
+                state.busy -= 1;
+                true
+            } else {
+                false
             }
         };
 
-        let is_blocked = |pos: Pos2| {
-            if !matches!(
-                map[pos.x as usize + pos.y as usize * BOARD_SIZE],
-                MapCell::Empty(_)
-            ) {
-                return true;
+        if !busy {
+            // The keyboard takes the place of the script of the player's raccoon, which stays
+            // suspended until the player lets go of it.
+            let action_code = if world.player.get() == Some(self.id) {
+                // Nor are its handlers called.
+                self.state.borrow_mut().events.clear();
+                world.player_action.take().map(|action| action.code())
+            } else {
+                self.run_script(world)
+            };
+            if self.state.borrow().dead {
+                // The script ended and took the raccoon with it.
+                return;
             }
-            if others.iter().any(|other| {
-                let Ok(other_state) = other.state.try_borrow() else {
-                    return false;
-                };
-                other_state.pos == pos
-            }) {
-                return true;
+            let action = action_code.and_then(Action::from_code);
+            let result = match (action_code, action) {
+                (None, _) => ActionResult::None,
+                (Some(_), None) => ActionResult::Invalid,
+                (Some(_), Some(action)) => self.perform(action, world, life),
+            };
+            let mut state = self.state.borrow_mut();
+            state.last_action = result;
+            match (result, action) {
+                (ActionResult::BlockedByWall | ActionResult::OutOfBounds, Some(action)) => {
+                    notify(&mut state.events, Event::Blocked(action.code()));
+                }
+                // Moves are settled later by `resolve_moves`, once every agent has decided.
+                (_, Some(Action::Move(_))) => {}
+                (ActionResult::Done, Some(action)) => {
+                    state.busy += action.duration() - 1;
+                    state.satiety -= action.cost();
+                    if action != Action::Wait {
+                        state.stuck = 0;
+                    }
+                    match action {
+                        Action::Eat => notify(&mut state.events, Event::Eat),
+                        Action::EnterHole => notify(&mut state.events, Event::EnterHole),
+                        _ => {}
+                    }
+                }
+                _ => {}
             }
-            false
+        }
+
+        if self.kind == AgentKind::Predator {
+            return;
+        }
+
+        // Getting hungry over time
+        let mut state = self.state.borrow_mut();
+        state.satiety = (state.satiety - life.hunger_rate).max(0.).min(1.);
+        let hungry = state.satiety < HUNGRY_SATIETY;
+        if hungry && !state.hungry {
+            notify(&mut state.events, Event::Hungry);
+        }
+        state.hungry = hungry;
+
+        state.age += 1;
+        state.satiety_sum += state.satiety as f64;
+        state.birth_cooldown = state.birth_cooldown.saturating_sub(1);
+        if state.satiety <= 0. {
+            state.starving += 1;
+            state.starving_total += 1;
+        } else {
+            state.starving = 0;
+        }
+
+        let cause = if life.starvation_ticks <= state.starving {
+            "starvation"
+        } else if life.max_age <= state.age {
+            "old age"
+        } else {
+            return;
         };
+        self.die(&mut state, world, cause);
+    }
 
-        let prev_pos = self.state.borrow().pos;
-        if let Some(direction) = direction_code.and_then(|code| DIRECTIONS.get(code as usize)) {
-            let mut state = self.state.borrow_mut();
-            let mut pos = state.pos + *direction;
+    fn die(&self, state: &mut RaccoonState, world: &SharedWorld, cause: &'static str) {
+        state.dead = true;
+        state.cause_of_death = Some(cause);
+        if state.in_hole {
+            let holes = world.holes.borrow();
+            if let Some(hole) = holes.iter().find(|hole| hole.pos == state.pos) {
+                hole.occupied.set(false);
+            }
+        }
+        log_event!("Raccoon {} died of {cause} at age {}", self.id, state.age);
+    }
 
-            if pos.x < 0. {
-                pos.x = 0.;
-            } else if BOARD_SIZE as f32 <= pos.x {
-                pos.x = (BOARD_SIZE - 1) as f32;
+    /// Queue a value broadcast by a raccoon of the same team for the script's `on_message`.
+    pub(crate) fn receive(&self, value: i64) {
+        notify(&mut self.state.borrow_mut().events, Event::Message(value));
+    }
+
+    /// Give birth to a new raccoon next to this one if it is well fed and resting in a hole.
+    /// The newborn runs its own VM on the parent's bytecode.
+    pub(crate) fn reproduce(
+        &self,
+        id: usize,
+        occupied: impl Fn(Pos2) -> bool,
+        world: &SharedWorld,
+        life: &LifeCycle,
+    ) -> Option<Raccoon> {
+        let pos = {
+            let state = self.state.borrow();
+            if self.kind != AgentKind::Raccoon
+                || state.dead
+                || !state.in_hole
+                || state.age < life.maturity
+                || state.satiety < life.birth_satiety
+                || 0 < state.birth_cooldown
+            {
+                return None;
             }
-            if pos.y < 0. {
-                pos.y = 0.;
-            } else if BOARD_SIZE as f32 <= pos.y {
-                pos.y = (BOARD_SIZE - 1) as f32;
+            state.pos
+        };
+
+        let birth_pos = DIRECTIONS[..4]
+            .iter()
+            .map(|direction| pos + *direction)
+            .find(|pos| {
+                in_bounds(*pos) && is_passable(*pos, &world.map.borrow()) && !occupied(*pos)
+            })?;
+
+        let child = Raccoon::new(
+            id,
+            AgentKind::Raccoon,
+            world,
+            &self.bytecode,
+            self.state.borrow().step_budget,
+            self.debug_output,
+        );
+        {
+            let mut parent = self.state.borrow_mut();
+            let mut state = child.state.borrow_mut();
+            parent.satiety -= life.birth_cost;
+            parent.birth_cooldown = life.birth_interval;
+            state.pos = birth_pos;
+            state.tint = parent.tint;
+            state.satiety = life.birth_cost;
+            state.generation = parent.generation + 1;
+            state.team = parent.team;
+            state.restart_policy = parent.restart_policy;
+        }
+        log_event!("Raccoon {} gave birth to raccoon {id}", self.id);
+        Some(child)
+    }
+
+    /// Resume the script until it yields the next action code. Handlers of pending events run
+    /// first, and a handler that yields an action takes the turn of the tasks.
+    fn run_script(&self, world: &SharedWorld) -> Option<i64> {
+        {
+            let mut state = self.state.borrow_mut();
+            if state.halted {
+                state.events.clear();
+                return None;
             }
+            state.steps = 0;
+        }
+        let action_code = match self.run_handlers(world) {
+            Some(action_code) => action_code,
+            None => self.run_tasks(world),
+        };
+
+        let mut state = self.state.borrow_mut();
+        state.misbehaving = state.step_budget < state.steps;
+        action_code
+    }
 
-            if !is_blocked(pos) {
-                state.pos = pos;
+    /// Resume the tasks in order of priority until one yields an action other than waiting.
+    fn run_tasks(&self, world: &SharedWorld) -> Option<i64> {
+        let mut tasks = self.tasks.borrow_mut();
+        let mut action_code = None;
+        let mut stopped = vec![];
+        for task in tasks.iter_mut() {
+            if task.id == MAIN_TASK && task.vm.top().is_err() {
+                task.location = None;
+                if let Err(e) = task.vm.init_fn("main", &[]) {
+                    self.script_error(world, "main", None, format!("init_fn: {e}"));
+                    if self.main_ended(world, "script error") {
+                        continue;
+                    }
+                    break;
+                }
             }
+            let resumed = self.resume(world, &mut task.vm, &task.function, &mut task.location);
+            match resumed {
+                Resumed::Yielded(code) => {
+                    action_code = code.or(action_code);
+                    let waiting =
+                        code.map_or(true, |code| Action::from_code(code) == Some(Action::Wait));
+                    if !waiting {
+                        break;
+                    }
+                }
+                Resumed::Finished | Resumed::Failed if task.id == MAIN_TASK => {
+                    let cause = if matches!(resumed, Resumed::Failed) {
+                        "script error"
+                    } else {
+                        "script end"
+                    };
+                    if !self.main_ended(world, cause) {
+                        break;
+                    }
+                }
+                Resumed::Finished | Resumed::Failed => stopped.push(task.id),
+                // The budget for the tick is spent.
+                Resumed::Preempted => {
+                    action_code = None;
+                    break;
+                }
+            }
+        }
+        if self.state.borrow().halted {
+            // Only `main` is kept, to start over if the raccoon is given another policy.
+            tasks.retain(|task| task.id == MAIN_TASK);
+            let mut state = self.state.borrow_mut();
+            state.task_requests.clear();
+            state.task_count = 1;
+            return None;
         }
+        tasks.retain(|task| !stopped.contains(&task.id));
+        self.apply_task_requests(&mut tasks, world);
+        action_code
+    }
 
+    /// Follow the restart policy once `main` has run to its end or failed. `cause` is the cause
+    /// of death under `RestartPolicy::Die`. Returns whether the other tasks carry on.
+    fn main_ended(&self, world: &SharedWorld, cause: &'static str) -> bool {
         let mut state = self.state.borrow_mut();
-        let mut items = items.borrow_mut();
-        if let Some((i, _)) = items
-            .iter()
-            .enumerate()
-            .find(|(_, item)| **item == state.pos)
-        {
-            items.remove(i);
-            state.ate += 1;
-            state.satiety += CORN_ENERGY;
-            println!(
-                "Raccoon {} ate {} corns and satiety became {}",
-                self.id, state.ate, state.satiety
-            );
+        match state.restart_policy {
+            // The VM of `main` has no frame left, so `run_tasks` starts it over next time.
+            RestartPolicy::Restart => {
+                state.restarts += 1;
+                log_event!(
+                    "Raccoon {} restarted its script ({} times)",
+                    self.id,
+                    state.restarts
+                );
+            }
+            RestartPolicy::Halt => {
+                state.halted = true;
+                log_event!("Raccoon {} halted its script", self.id);
+            }
+            RestartPolicy::Die => self.die(&mut state, world, cause),
         }
+        state.restart_policy == RestartPolicy::Restart
+    }
 
-        // Getting hungry over time
-        state.satiety = (state.satiety - HUNGER_RATE).max(0.).min(1.);
+    /// Start and stop the tasks that the script asked for.
+    fn apply_task_requests(&self, tasks: &mut Vec<Task>, world: &SharedWorld) {
+        let requests = std::mem::take(&mut self.state.borrow_mut().task_requests);
+        for request in requests {
+            match request {
+                TaskRequest::Spawn {
+                    id,
+                    function,
+                    priority,
+                } => {
+                    let mut vm = script_vm(
+                        self.id,
+                        &self.state,
+                        world,
+                        &self.bytecode,
+                        self.debug_output,
+                    );
+                    if let Err(e) = vm.init_fn(&function, &[]) {
+                        self.script_error(world, &function, None, format!("spawn_task: {e}"));
+                        continue;
+                    }
+                    let task = Task {
+                        id,
+                        function,
+                        priority,
+                        vm,
+                        location: None,
+                    };
+                    let index = insertion_index(tasks.iter().map(|task| task.priority), priority);
+                    tasks.insert(index, task);
+                }
+                TaskRequest::Stop(id) => {
+                    tasks.retain(|task| task.id != id || task.id == MAIN_TASK);
+                }
+            }
+        }
+        self.state.borrow_mut().task_count = tasks.len();
+    }
 
-        if prev_pos != state.pos {
-            if let Some(hole) = holes.iter().find(|hole| prev_pos == hole.pos) {
-                hole.occupied.set(false);
+    /// Run the handlers of the pending events in order, until one yields. Returns what it
+    /// yielded, or `None` if no handler is left for this tick.
+    fn run_handlers(&self, world: &SharedWorld) -> Option<Option<i64>> {
+        let mut vm = self.handler_vm.borrow_mut();
+        loop {
+            let handler = match self.handling.get() {
+                Some(handler) => handler,
+                None => {
+                    let event = self.state.borrow_mut().events.pop_front()?;
+                    // A script without a handler for the event ignores it.
+                    if vm.init_fn(event.handler(), &event.args()).is_err() {
+                        continue;
+                    }
+                    self.handler_location.set(None);
+                    event.handler()
+                }
+            };
+            self.handling.set(Some(handler));
+            let mut location = self.handler_location.get();
+            let resumed = self.resume(world, &mut vm, handler, &mut location);
+            self.handler_location.set(location);
+            match resumed {
+                Resumed::Yielded(action_code) => return Some(action_code),
+                Resumed::Finished | Resumed::Failed => self.handling.set(None),
+                // The handler carries on in the next turn, and the budget for this one is spent.
+                Resumed::Preempted => return Some(None),
             }
         }
+    }
 
-        if let Some(hole) = holes.iter().find(|hole| state.pos == hole.pos) {
-            hole.occupied.set(true);
+    /// Resume a coroutine of the script running `function` until it yields, finishes, fails or
+    /// is pre-empted. `location` is the checkpoint the coroutine passed last, and is kept up to
+    /// date.
+    fn resume(
+        &self,
+        world: &SharedWorld,
+        vm: &mut Vm,
+        function: &str,
+        location: &mut Option<Location>,
+    ) -> Resumed {
+        self.state.borrow_mut().checkpoint = *location;
+        let result = vm.interpret();
+        *location = self.state.borrow().checkpoint;
+        match result {
+            Ok(YieldResult::Finished(_)) => Resumed::Finished,
+            Ok(YieldResult::Suspend(res)) => {
+                let mut state = self.state.borrow_mut();
+                if !state.preempting {
+                    return Resumed::Yielded(res.coerce_i64().ok());
+                }
+                state.preempting = false;
+                state.preempted += 1;
+                if logging_events() {
+                    eprintln!(
+                        "Raccoon {} exceeded its step budget of {} and was pre-empted ({} times)",
+                        self.id, state.step_budget, state.preempted
+                    );
+                }
+                Resumed::Preempted
+            }
+            Err(e) => {
+                self.script_error(world, function, *location, e);
+                Resumed::Failed
+            }
         }
     }
+
+    /// Report an error of the script in `function`, in the block at `location` if it is known,
+    /// and add it to the raccoon's log.
+    fn script_error(
+        &self,
+        world: &SharedWorld,
+        function: &str,
+        location: Option<Location>,
+        e: impl std::fmt::Display,
+    ) {
+        let error = ScriptError {
+            tick: world.ticks.get(),
+            function: function.to_string(),
+            location,
+            message: e.to_string(),
+        };
+        // The error stays in the log and the counts when nobody reads the output.
+        if logging_events() {
+            eprintln!("Error in raccoon {}: {error}", self.id);
+        }
+        let mut state = self.state.borrow_mut();
+        state.script_errors += 1;
+        if MAX_LOGGED_ERRORS <= state.error_log.len() {
+            state.error_log.pop_front();
+        }
+        state.error_log.push_back(error);
+    }
+
+    /// Validate an action against the world and apply it if possible. This is the only place
+    /// where a raccoon's actions change the world.
+    fn perform(&self, action: Action, world: &SharedWorld, life: &LifeCycle) -> ActionResult {
+        let (items, holes) = (&world.items, &world.holes);
+        if self.kind == AgentKind::Predator && !matches!(action, Action::Move(_) | Action::Wait) {
+            return ActionResult::Invalid;
+        }
+        let mut state = self.state.borrow_mut();
+        let hole_here = |pos: Pos2| holes.borrow().iter().position(|hole| hole.pos == pos);
+        let item_here = |pos: Pos2| items.borrow().iter().position(|item| *item == pos);
+
+        match action {
+            Action::Move(direction) => {
+                if state.in_hole {
+                    return ActionResult::Invalid;
+                }
+                let Some(step) = world.movement.get().directions().get(direction as usize) else {
+                    return ActionResult::Invalid;
+                };
+                let pos = state.pos + *step;
+                if !in_bounds(pos) {
+                    return ActionResult::OutOfBounds;
+                }
+                let map = world.map.borrow();
+                if !pathfinding::can_step(&map, [state.pos.x as i32, state.pos.y as i32], *step) {
+                    return ActionResult::BlockedByWall;
+                }
+                // Other agents may be in the way, which `resolve_moves` sorts out for everyone
+                // at once.
+                state.planned_move = Some(pos);
+            }
+            Action::Wait => {}
+            Action::Eat => {
+                // Prefer a corn on the ground, then the storage of the hole we are in, and
+                // finally the one we are carrying.
+                if let Some(i) = item_here(state.pos) {
+                    items.borrow_mut().remove(i);
+                    state.collected += 1;
+                } else if let Some(hole) = holes.borrow().iter().find(|hole| {
+                    state.in_hole
+                        && hole.pos == state.pos
+                        && hole.team == state.team
+                        && 0 < hole.stored.get()
+                }) {
+                    hole.stored.set(hole.stored.get() - 1);
+                } else if 0 < state.carrying {
+                    state.carrying -= 1;
+                } else {
+                    return ActionResult::Invalid;
+                }
+                state.ate += 1;
+                state.satiety += life.corn_energy;
+                log_event!(
+                    "Raccoon {} ate {} corns and satiety became {}",
+                    self.id,
+                    state.ate,
+                    state.satiety
+                );
+            }
+            Action::PickUp => {
+                if CARRY_CAPACITY <= state.carrying {
+                    return ActionResult::Invalid;
+                }
+                let Some(i) = item_here(state.pos) else {
+                    return ActionResult::Invalid;
+                };
+                items.borrow_mut().remove(i);
+                state.carrying += 1;
+                state.collected += 1;
+            }
+            Action::Drop => {
+                if state.carrying == 0 {
+                    return ActionResult::Invalid;
+                }
+                if state.in_hole {
+                    // Dropping in a hole stores the corn for later.
+                    let holes = holes.borrow();
+                    let Some(hole) = holes.iter().find(|hole| hole.pos == state.pos) else {
+                        return ActionResult::Invalid;
+                    };
+                    if hole.capacity <= hole.stored.get() {
+                        return ActionResult::Invalid;
+                    }
+                    hole.stored.set(hole.stored.get() + 1);
+                    state.delivered += 1;
+                } else {
+                    if item_here(state.pos).is_some() {
+                        return ActionResult::Invalid;
+                    }
+                    items.borrow_mut().push(state.pos);
+                }
+                state.carrying -= 1;
+            }
+            Action::EnterHole => {
+                let holes = holes.borrow();
+                let Some(hole) = holes.iter().find(|hole| hole.pos == state.pos) else {
+                    return ActionResult::Invalid;
+                };
+                // Only the team that owns the hole can hide in it.
+                if state.in_hole || hole.occupied.get() || hole.team != state.team {
+                    return ActionResult::Invalid;
+                }
+                hole.occupied.set(true);
+                state.in_hole = true;
+            }
+            Action::LeaveHole => {
+                if !state.in_hole {
+                    return ActionResult::Invalid;
+                }
+                if let Some(hole) = holes.borrow().iter().find(|hole| hole.pos == state.pos) {
+                    hole.occupied.set(false);
+                }
+                state.in_hole = false;
+            }
+            Action::Dig => {
+                if state.in_hole || hole_here(state.pos).is_some() || item_here(state.pos).is_some()
+                {
+                    return ActionResult::Invalid;
+                }
+                holes.borrow_mut().push(Hole::new(state.pos, state.team));
+            }
+        }
+        ActionResult::Done
+    }
+}
+
+fn in_bounds(pos: Pos2) -> bool {
+    0. <= pos.x && pos.x < BOARD_SIZE as f32 && 0. <= pos.y && pos.y < BOARD_SIZE as f32
+}
+
+fn is_passable(pos: Pos2, map: &[MapCell]) -> bool {
+    map[pos.x as usize + pos.y as usize * BOARD_SIZE].is_passable()
+}
+
+/// A VM to run a coroutine of the agent's script on.
+fn script_vm(
+    id: usize,
+    state: &Rc<RefCell<RaccoonState>>,
+    world: &SharedWorld,
+    bytecode: &Rc<ByteCode>,
+    debug_output: bool,
+) -> Vm {
+    Vm::new(
+        bytecode.clone(),
+        Box::new(VmUserData {
+            id,
+            state: state.clone(),
+            world: world.clone(),
+        }),
+        debug_output,
+    )
+}
+
+/// Downcast the VM's user data and charge one step against the script's budget for this tick.
+/// The script yields at its next checkpoint once the budget is exhausted.
+fn user_data(data: &dyn Any) -> Option<&VmUserData> {
+    let data = data.downcast_ref::<VmUserData>()?;
+    data.state.borrow_mut().steps += 1;
+    Some(data)
+}
+
+/// Compile the raccoon script `source`, where `src` is the file name for error messages.
+pub(crate) fn compile_program(
+    src: &str,
+    source: &str,
+    disasm: bool,
+) -> Result<ByteCode, Box<dyn Error>> {
+    compile_with_natives(src, source, disasm, extend_funcs)
 }
 
-pub(crate) fn compile_program(args: &Args) -> Result<ByteCode, Box<dyn Error>> {
-    let src = args.source.as_ref().expect("Source file exists");
-    let source = std::fs::read_to_string(src).expect("Source file could be read");
-    let ast = parse_program(src, &source).expect("Source parsed");
+/// Compile a script that can call the native functions registered by `natives`.
+pub(crate) fn compile_with_natives(
+    src: &str,
+    source: &str,
+    disasm: bool,
+    natives: fn(&mut dyn FnMut(String, NativeFn<'static>)),
+) -> Result<ByteCode, Box<dyn Error>> {
+    let source = preempt::instrument(source);
+    let ast = parse_program(src, &source).map_err(|e| format!("{src}: {e:?}"))?;
 
     let mut type_check_context = TypeCheckContext::new();
-    extend_funcs(|name, func| type_check_context.add_fn(name, func));
+    natives(&mut |name: String, func: NativeFn<'static>| type_check_context.add_fn(name, func));
     match type_check(&ast, &mut type_check_context) {
-        Ok(_) => println!("Typecheck Ok"),
+        Ok(_) => {
+            log_event!("Typecheck Ok");
+        }
         Err(e) => {
             return Err(format!(
                 "{}:{}:{}: {}",
@@ -222,12 +976,12 @@ pub(crate) fn compile_program(args: &Args) -> Result<ByteCode, Box<dyn Error>> {
     let mut compiler = Compiler::new();
     compiler.compile(&ast)?;
 
-    if args.disasm {
+    if disasm {
         compiler.disasm(&mut std::io::stdout())?;
     }
 
     let mut bytecode = compiler.into_bytecode();
-    extend_funcs(|name, func| bytecode.add_fn(name, func));
+    natives(&mut |name: String, func: NativeFn<'static>| bytecode.add_fn(name, func));
 
     Ok(bytecode)
 }
@@ -237,7 +991,7 @@ fn get_prop_fn(get: fn(&RaccoonState) -> i64) -> NativeFn<'static> {
         vec![],
         TypeDecl::I64,
         Box::new(move |state, _| {
-            if let Some(data) = state.downcast_ref::<VmUserData>() {
+            if let Some(data) = user_data(state) {
                 Value::I64(get(&data.state.borrow()))
             } else {
                 Value::I64(0)
@@ -251,7 +1005,7 @@ fn get_prop_fn_f(get: fn(&RaccoonState) -> f64) -> NativeFn<'static> {
         vec![],
         TypeDecl::F64,
         Box::new(move |data, _| {
-            if let Some(data) = data.downcast_ref::<VmUserData>() {
+            if let Some(data) = user_data(data) {
                 Value::F64(get(&data.state.borrow()))
             } else {
                 Value::F64(0.)
@@ -260,7 +1014,75 @@ fn get_prop_fn_f(get: fn(&RaccoonState) -> f64) -> NativeFn<'static> {
     )
 }
 
-fn extend_funcs(mut proc: impl FnMut(String, NativeFn<'static>)) {
+/// A native function that looks at the other raccoons of the same team, if `friendly`, or of
+/// the other teams. `get` receives their positions, ordered by id, and the index argument if the
+/// function is `indexed`.
+fn team_fn(friendly: bool, indexed: bool, get: fn(&[Pos2], usize) -> i64) -> NativeFn<'static> {
+    let args = if indexed {
+        vec![("i", TypeDecl::I64)]
+    } else {
+        vec![]
+    };
+    NativeFn::new(
+        args,
+        TypeDecl::I64,
+        Box::new(move |data, args| {
+            let Some(data) = user_data(data) else {
+                return Value::I64(-1);
+            };
+            let team = data.state.borrow().team;
+            let positions: Vec<_> = data
+                .world
+                .agents
+                .borrow()
+                .iter()
+                .filter(|agent| {
+                    agent.id != data.id
+                        && agent.kind == AgentKind::Raccoon
+                        && (agent.team == team) == friendly
+                })
+                .map(|agent| agent.pos)
+                .collect();
+            let i = args
+                .first()
+                .and_then(|arg| arg.coerce_i64().ok())
+                .and_then(|i| usize::try_from(i).ok())
+                .unwrap_or(usize::MAX);
+            Value::I64(get(&positions, i))
+        }),
+    )
+}
+
+/// The location a checkpoint passes as its arguments.
+pub(crate) fn checkpoint_location(args: &[Value]) -> Option<Location> {
+    let arg = |i: usize| {
+        args.get(i)
+            .and_then(|arg| arg.coerce_i64().ok())
+            .and_then(|n| usize::try_from(n).ok())
+    };
+    Some(Location {
+        line: arg(0)?,
+        column: arg(1)?,
+    })
+}
+
+fn extend_funcs(proc: &mut dyn FnMut(String, NativeFn<'static>)) {
+    proc(
+        CHECKPOINT_FN.to_string(),
+        NativeFn::new(
+            vec![("line", TypeDecl::I64), ("column", TypeDecl::I64)],
+            TypeDecl::I64,
+            Box::new(|data, args| {
+                let Some(data) = user_data(data) else {
+                    return Value::I64(0);
+                };
+                let mut state = data.state.borrow_mut();
+                state.checkpoint = checkpoint_location(args);
+                state.preempting = state.step_budget < state.steps;
+                Value::I64(state.preempting as i64)
+            }),
+        ),
+    );
     proc("get_x".to_string(), get_prop_fn(|state| state.pos.x as i64));
     proc("get_y".to_string(), get_prop_fn(|state| state.pos.y as i64));
     proc(
@@ -269,13 +1091,9 @@ fn extend_funcs(mut proc: impl FnMut(String, NativeFn<'static>)) {
             vec![],
             TypeDecl::I64,
             Box::new(move |state, _| {
-                if let Some(data) = state.downcast_ref::<VmUserData>() {
+                if let Some(data) = user_data(state) {
                     let mut state = data.state.borrow_mut();
-                    state.path = find_path(
-                        [state.pos.x as i32, state.pos.y as i32],
-                        &data.map,
-                        &data.items.borrow(),
-                    );
+                    find_path(data, &mut state, &data.world.items.borrow());
                     Value::I64(state.path.is_some() as i64)
                 } else {
                     Value::I64(0)
@@ -289,21 +1107,22 @@ fn extend_funcs(mut proc: impl FnMut(String, NativeFn<'static>)) {
             vec![],
             TypeDecl::I64,
             Box::new(move |state, _| {
-                if let Some(data) = state.downcast_ref::<VmUserData>() {
+                if let Some(data) = user_data(state) {
                     let mut state = data.state.borrow_mut();
                     let holes: Vec<_> = data
+                        .world
                         .holes
+                        .borrow()
                         .iter()
                         .filter_map(|hole| {
-                            if hole.occupied.get() {
+                            if hole.occupied.get() || hole.team != state.team {
                                 None
                             } else {
                                 Some(hole.pos)
                             }
                         })
                         .collect();
-                    state.path =
-                        find_path([state.pos.x as i32, state.pos.y as i32], &data.map, &holes);
+                    find_path(data, &mut state, &holes);
                     Value::I64(state.path.is_some() as i64)
                 } else {
                     Value::I64(0)
@@ -311,15 +1130,103 @@ fn extend_funcs(mut proc: impl FnMut(String, NativeFn<'static>)) {
             }),
         ),
     );
+    proc(
+        "find_path_to".to_string(),
+        NativeFn::new(
+            vec![("x", TypeDecl::I64), ("y", TypeDecl::I64)],
+            TypeDecl::I64,
+            Box::new(move |state, args| {
+                if let Some(data) = user_data(state) {
+                    let mut state = data.state.borrow_mut();
+                    let Some(target) = arg_pos(args, 0) else {
+                        // Keep the raccoon from walking on towards the previous target.
+                        clear_path(data, &mut state);
+                        return Value::I64(0);
+                    };
+                    find_path(data, &mut state, &[target]);
+                    Value::I64(state.path.is_some() as i64)
+                } else {
+                    Value::I64(0)
+                }
+            }),
+        ),
+    );
+    proc(
+        "path_exists".to_string(),
+        NativeFn::new(
+            vec![("x", TypeDecl::I64), ("y", TypeDecl::I64)],
+            TypeDecl::I64,
+            Box::new(move |state, args| {
+                if let Some(data) = user_data(state) {
+                    let Some(target) = arg_pos(args, 0) else {
+                        return Value::I64(0);
+                    };
+                    let mut state = data.state.borrow_mut();
+                    let path = search_path(data, &mut state, &[target]);
+                    Value::I64(path.is_some() as i64)
+                } else {
+                    Value::I64(0)
+                }
+            }),
+        ),
+    );
+    proc(
+        "path_length".to_string(),
+        get_prop_fn(|state| path_steps(&state.path)),
+    );
+    proc(
+        "clear_path".to_string(),
+        NativeFn::new(
+            vec![],
+            TypeDecl::I64,
+            Box::new(move |state, _| {
+                if let Some(data) = user_data(state) {
+                    clear_path(data, &mut data.state.borrow_mut());
+                }
+                Value::I64(0)
+            }),
+        ),
+    );
+    proc(
+        "distance_to_nearest_hole".to_string(),
+        NativeFn::new(
+            vec![],
+            TypeDecl::I64,
+            Box::new(move |state, _| {
+                if let Some(data) = user_data(state) {
+                    let mut state = data.state.borrow_mut();
+                    let holes: Vec<_> = data
+                        .world
+                        .holes
+                        .borrow()
+                        .iter()
+                        .filter(|hole| hole.team == state.team)
+                        .filter(|hole| !hole.occupied.get() || hole.pos == state.pos)
+                        .map(|hole| hole.pos)
+                        .collect();
+                    let path = search_path(data, &mut state, &holes);
+                    Value::I64(path_steps(&path))
+                } else {
+                    Value::I64(-1)
+                }
+            }),
+        ),
+    );
     proc(
         "is_at_hole".to_string(),
         NativeFn::new(
             vec![],
             TypeDecl::I64,
             Box::new(move |state, _| {
-                if let Some(data) = state.downcast_ref::<VmUserData>() {
+                if let Some(data) = user_data(state) {
                     let state = data.state.borrow();
-                    Value::I64((data.holes.iter().any(|hole| state.pos == hole.pos)) as i64)
+                    let holes = data.world.holes.borrow();
+                    Value::I64(
+                        (holes
+                            .iter()
+                            .any(|hole| state.pos == hole.pos && hole.team == state.team))
+                            as i64,
+                    )
                 } else {
                     Value::I64(0)
                 }
@@ -332,14 +1239,13 @@ fn extend_funcs(mut proc: impl FnMut(String, NativeFn<'static>)) {
             vec![],
             TypeDecl::I64,
             Box::new(move |state, _| {
-                if let Some(data) = state.downcast_ref::<VmUserData>() {
+                if let Some(data) = user_data(state) {
                     let mut state = data.state.borrow_mut();
                     if let Some(node) = state.path.as_mut().and_then(|path| path.pop()) {
-                        // println!("get_next_move returning {}", node.direction);
-                        return Value::I64(node.direction as i64);
+                        return Value::I64(node.direction.map_or(ACTION_WAIT, move_code));
                     }
                 }
-                Value::I64(5)
+                Value::I64(ACTION_WAIT)
             }),
         ),
     );
@@ -347,81 +1253,282 @@ fn extend_funcs(mut proc: impl FnMut(String, NativeFn<'static>)) {
         "get_satiety".to_string(),
         get_prop_fn_f(|state| state.satiety as f64),
     );
+    proc(
+        "is_at_corn".to_string(),
+        NativeFn::new(
+            vec![],
+            TypeDecl::I64,
+            Box::new(move |state, _| {
+                if let Some(data) = user_data(state) {
+                    let state = data.state.borrow();
+                    let items = data.world.items.borrow();
+                    Value::I64((items.iter().any(|item| state.pos == *item)) as i64)
+                } else {
+                    Value::I64(0)
+                }
+            }),
+        ),
+    );
+    proc(
+        "is_in_hole".to_string(),
+        get_prop_fn(|state| state.in_hole as i64),
+    );
+    proc(
+        "nearest_raccoon_path".to_string(),
+        NativeFn::new(
+            vec![],
+            TypeDecl::I64,
+            Box::new(move |state, _| {
+                if let Some(data) = user_data(state) {
+                    let targets: Vec<_> = data
+                        .world
+                        .agents
+                        .borrow()
+                        .iter()
+                        .filter(|agent| {
+                            agent.id != data.id
+                                && agent.kind == AgentKind::Raccoon
+                                && !agent.in_hole
+                        })
+                        .map(|agent| agent.pos)
+                        .collect();
+                    let mut state = data.state.borrow_mut();
+                    find_path(data, &mut state, &targets);
+                    Value::I64(state.path.is_some() as i64)
+                } else {
+                    Value::I64(0)
+                }
+            }),
+        ),
+    );
+    proc(
+        "get_team".to_string(),
+        get_prop_fn(|state| state.team as i64),
+    );
+    proc(
+        "count_friends".to_string(),
+        team_fn(true, false, |positions, _| positions.len() as i64),
+    );
+    proc(
+        "count_enemies".to_string(),
+        team_fn(false, false, |positions, _| positions.len() as i64),
+    );
+    proc(
+        "friend_x".to_string(),
+        team_fn(true, true, |positions, i| {
+            positions.get(i).map_or(-1, |pos| pos.x as i64)
+        }),
+    );
+    proc(
+        "friend_y".to_string(),
+        team_fn(true, true, |positions, i| {
+            positions.get(i).map_or(-1, |pos| pos.y as i64)
+        }),
+    );
+    proc(
+        "enemy_x".to_string(),
+        team_fn(false, true, |positions, i| {
+            positions.get(i).map_or(-1, |pos| pos.x as i64)
+        }),
+    );
+    proc(
+        "enemy_y".to_string(),
+        team_fn(false, true, |positions, i| {
+            positions.get(i).map_or(-1, |pos| pos.y as i64)
+        }),
+    );
+    proc(
+        "spawn_task".to_string(),
+        NativeFn::new(
+            vec![("function", TypeDecl::Str), ("priority", TypeDecl::I64)],
+            TypeDecl::I64,
+            Box::new(move |data, args| {
+                let Some(data) = user_data(data) else {
+                    return Value::I64(-1);
+                };
+                let (Some(Value::Str(function)), Some(priority)) = (args.first(), args.get(1))
+                else {
+                    return Value::I64(-1);
+                };
+                let priority = priority.coerce_i64().unwrap_or(0);
+                let mut state = data.state.borrow_mut();
+                let spawning = state
+                    .task_requests
+                    .iter()
+                    .filter(|request| matches!(request, TaskRequest::Spawn { .. }))
+                    .count();
+                if MAX_TASKS <= state.task_count + spawning {
+                    return Value::I64(-1);
+                }
+                let id = state.next_task_id;
+                state.next_task_id += 1;
+                state.task_requests.push(TaskRequest::Spawn {
+                    id,
+                    function: function.clone(),
+                    priority,
+                });
+                Value::I64(id as i64)
+            }),
+        ),
+    );
+    proc(
+        "stop_task".to_string(),
+        NativeFn::new(
+            vec![("id", TypeDecl::I64)],
+            TypeDecl::I64,
+            Box::new(move |data, args| {
+                let Some(data) = user_data(data) else {
+                    return Value::I64(0);
+                };
+                let id = args.first().and_then(|id| id.coerce_i64().ok());
+                let Some(id) = id.and_then(|id| usize::try_from(id).ok()) else {
+                    return Value::I64(0);
+                };
+                let mut state = data.state.borrow_mut();
+                state.task_requests.push(TaskRequest::Stop(id));
+                Value::I64(1)
+            }),
+        ),
+    );
+    proc(
+        "broadcast".to_string(),
+        NativeFn::new(
+            vec![("value", TypeDecl::I64)],
+            TypeDecl::I64,
+            Box::new(move |data, args| {
+                let Some(data) = user_data(data) else {
+                    return Value::I64(0);
+                };
+                let value = args.first().and_then(|v| v.coerce_i64().ok()).unwrap_or(0);
+                data.world.messages.borrow_mut().push(Message {
+                    from: data.id,
+                    team: data.state.borrow().team,
+                    value,
+                });
+                Value::I64(1)
+            }),
+        ),
+    );
+    proc(
+        "get_carrying".to_string(),
+        get_prop_fn(|state| state.carrying as i64),
+    );
+    proc(
+        "get_stored".to_string(),
+        NativeFn::new(
+            vec![],
+            TypeDecl::I64,
+            Box::new(move |state, _| {
+                if let Some(data) = user_data(state) {
+                    let state = data.state.borrow();
+                    let holes = data.world.holes.borrow();
+                    let stored = hole_inside(&holes, &state).map_or(0, |hole| hole.stored.get());
+                    Value::I64(stored as i64)
+                } else {
+                    Value::I64(0)
+                }
+            }),
+        ),
+    );
+    proc(
+        "get_hole_capacity".to_string(),
+        NativeFn::new(
+            vec![],
+            TypeDecl::I64,
+            Box::new(move |state, _| {
+                if let Some(data) = user_data(state) {
+                    let state = data.state.borrow();
+                    let holes = data.world.holes.borrow();
+                    let capacity = hole_inside(&holes, &state).map_or(0, |hole| hole.capacity);
+                    Value::I64(capacity as i64)
+                } else {
+                    Value::I64(0)
+                }
+            }),
+        ),
+    );
+    // `last_move_result` is the name scripts used before actions other than moves had results.
+    for name in ["last_action_result", "last_move_result"] {
+        proc(
+            name.to_string(),
+            get_prop_fn(|state| state.last_action as i64),
+        );
+    }
+    let results = RESULT_CONSTANTS
+        .into_iter()
+        .map(|(name, result)| (name, result as i64));
+    for (name, code) in ACTION_CONSTANTS.into_iter().chain(results) {
+        proc(
+            name.to_string(),
+            NativeFn::new(
+                vec![],
+                TypeDecl::I64,
+                Box::new(move |_, _| Value::I64(code)),
+            ),
+        );
+    }
 }
 
-fn find_path(start: [i32; 2], map: &[MapCell], items: &[Pos2]) -> Option<Vec<PathNode>> {
-    // println!("finding path for {items:?}");
-    let mut cost_map = [i32::MAX; BOARD_SIZE * BOARD_SIZE];
-    let mut came_from: [Option<u8>; BOARD_SIZE * BOARD_SIZE] = [None; BOARD_SIZE * BOARD_SIZE];
+/// Search a path from the raccoon's position to the nearest of `goals` with the world's choice of
+/// algorithm, and remember it along with the search statistics.
+fn find_path(data: &VmUserData, state: &mut RaccoonState, goals: &[Pos2]) {
+    state.path = search_path(data, state, goals);
+    let mut reservations = data.world.reservations.borrow_mut();
+    traffic::reserve(&mut reservations, data.id, state.pos, state.path.as_ref());
+}
 
-    #[derive(Eq, Ord)]
-    struct MinCost {
-        pos: [i32; 2],
-        cost: i32,
-    }
+/// The hole of the raccoon's team that it is in, if any.
+fn hole_inside<'a>(holes: &'a [Hole], state: &RaccoonState) -> Option<&'a Hole> {
+    holes
+        .iter()
+        .find(|hole| state.in_hole && hole.pos == state.pos && hole.team == state.team)
+}
 
-    impl PartialEq for MinCost {
-        fn eq(&self, other: &Self) -> bool {
-            self.cost.eq(&other.cost)
-        }
-    }
+/// Forget the current path, along with the cells reserved for it.
+fn clear_path(data: &VmUserData, state: &mut RaccoonState) {
+    state.path = None;
+    let mut reservations = data.world.reservations.borrow_mut();
+    traffic::reserve(&mut reservations, data.id, state.pos, None);
+}
 
-    impl PartialOrd for MinCost {
-        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-            Reverse(self.cost).partial_cmp(&Reverse(other.cost))
-        }
+/// Search a path like `find_path`, but only return it instead of following it.
+///
+/// With cooperative planning, the cells reserved by other agents are avoided if possible.
+fn search_path(data: &VmUserData, state: &mut RaccoonState, goals: &[Pos2]) -> Option<Path> {
+    let world = &data.world;
+    let start = [state.pos.x as i32, state.pos.y as i32];
+    let (algorithm, movement) = (world.path_algorithm.get(), world.movement.get());
+    let (mut path, mut stats) = if world.traffic.get().cooperative {
+        let map = traffic::avoiding_map(
+            &world.map.borrow(),
+            &world.reservations.borrow(),
+            data.id,
+            goals,
+        );
+        pathfinding::find_path(algorithm, movement, start, &map, goals)
+    } else {
+        (None, SearchStats::default())
+    };
+    if path.is_none() {
+        let (plain_path, plain_stats) =
+            pathfinding::find_path(algorithm, movement, start, &world.map.borrow(), goals);
+        path = plain_path;
+        stats.accumulate(&plain_stats);
     }
+    state.last_search = stats;
+    state.total_search.accumulate(&stats);
+    path
+}
 
-    let mut open_set = BinaryHeap::new();
-    open_set.push(MinCost {
-        pos: start,
-        cost: 0,
-    });
-    cost_map[(start[0] + start[1] * BOARD_SIZE_I) as usize] = 0;
-    while let Some(state) = open_set.pop() {
-        if let Some(goal) = items
-            .iter()
-            .find(|item| [item.x as i32, item.y as i32] == state.pos)
-        {
-            let mut path = vec![PathNode {
-                direction: 5,
-                pos: [goal.x as i32, goal.y as i32],
-            }];
-            let mut cur = [goal.x as i32, goal.y as i32];
-            while let Some(direction_idx) = came_from[(cur[0] + cur[1] * BOARD_SIZE_I) as usize] {
-                let direction = DIRECTIONS[direction_idx as usize];
-                let x = cur[0] + direction.x as i32;
-                let y = cur[1] + direction.y as i32;
-                path.push(PathNode {
-                    direction: (direction_idx + 2) % 4,
-                    pos: [x as i32, y as i32],
-                });
-                cur = [x as i32, y as i32];
-            }
-            // println!("find_path returning {path:?}");
-            return Some(path);
-        }
-        let prev_cost = state.cost;
-        for (direction, next) in DIRECTIONS.iter().enumerate() {
-            let next = [state.pos[0] + next.x as i32, state.pos[1] + next.y as i32];
-            if next[0] < 0 || BOARD_SIZE_I <= next[0] || next[1] < 0 || BOARD_SIZE_I <= next[1] {
-                continue;
-            }
-            let idx = (next[0] + next[1] * BOARD_SIZE_I) as usize;
-            if !matches!(map[idx], MapCell::Empty(_)) {
-                continue;
-            }
-            let cost_cell = &mut cost_map[idx];
-            if prev_cost + 1 < *cost_cell {
-                open_set.push(MinCost {
-                    pos: next,
-                    cost: prev_cost + 1,
-                });
-                *cost_cell = prev_cost + 1;
-                came_from[idx] = Some(((direction + 2) % 4) as u8);
-            }
-        }
-    }
-    println!("find_path returning None");
-    None
+/// Read the `idx`-th argument of a native function as a board position.
+fn arg_pos(args: &[Value], idx: usize) -> Option<Pos2> {
+    let x = args.get(idx)?.coerce_i64().ok()?;
+    let y = args.get(idx + 1)?.coerce_i64().ok()?;
+    let pos = pos2(x as f32, y as f32);
+    in_bounds(pos).then_some(pos)
+}
+
+/// Number of steps in a path, not counting the goal node that only says we arrived.
+fn path_steps(path: &Option<Path>) -> i64 {
+    path.as_ref().map_or(-1, |path| path.len() as i64 - 1)
 }
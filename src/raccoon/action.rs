@@ -0,0 +1,140 @@
+//! The action protocol between raccoon scripts and the host.
+//!
+//! A script yields an action code, which is decoded into an [`Action`] and validated in
+//! `Raccoon::perform`. Scripts should use the named constants registered by
+//! [`ACTION_CONSTANTS`] instead of bare integers, and likewise [`RESULT_CONSTANTS`] for what
+//! `last_action_result()` returns.
+
+/// An action that a raccoon can take in a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    /// Move one cell towards an index into `DIRECTIONS`.
+    Move(u8),
+    Wait,
+    /// Eat a corn on the current cell.
+    Eat,
+    /// Pick up a corn on the current cell and carry it.
+    PickUp,
+    /// Drop a carried corn on the current cell.
+    Drop,
+    EnterHole,
+    LeaveHole,
+    /// Dig a new hole on the current cell.
+    Dig,
+}
+
+pub(crate) const ACTION_WAIT: i64 = 4;
+const ACTION_EAT: i64 = 5;
+const ACTION_PICK_UP: i64 = 6;
+const ACTION_DROP: i64 = 7;
+const ACTION_ENTER_HOLE: i64 = 8;
+const ACTION_LEAVE_HOLE: i64 = 9;
+const ACTION_DIG: i64 = 10;
+
+/// Names and codes of the actions, exposed to scripts as constant functions.
+pub(crate) const ACTION_CONSTANTS: [(&str, i64); 15] = [
+    ("LEFT", 0),
+    ("UP", 1),
+    ("RIGHT", 2),
+    ("DOWN", 3),
+    ("WAIT", ACTION_WAIT),
+    ("EAT", ACTION_EAT),
+    ("PICK_UP", ACTION_PICK_UP),
+    ("DROP", ACTION_DROP),
+    ("ENTER_HOLE", ACTION_ENTER_HOLE),
+    ("LEAVE_HOLE", ACTION_LEAVE_HOLE),
+    ("DIG", ACTION_DIG),
+    ("UP_LEFT", 11),
+    ("UP_RIGHT", 12),
+    ("DOWN_RIGHT", 13),
+    ("DOWN_LEFT", 14),
+];
+
+/// Number of ticks digging a hole takes.
+const DIG_TICKS: u32 = 10;
+
+/// Diagonal moves come after the other actions, since the codes right after the straight
+/// moves were already taken.
+const DIAGONAL_OFFSET: i64 = 7;
+
+/// The action code of a move towards an index into `DIRECTIONS`.
+pub(crate) fn move_code(direction: u8) -> i64 {
+    match direction {
+        0..=3 => direction as i64,
+        _ => direction as i64 + DIAGONAL_OFFSET,
+    }
+}
+
+impl Action {
+    pub(crate) fn from_code(code: i64) -> Option<Self> {
+        Some(match code {
+            0..=3 => Self::Move(code as u8),
+            // -1 is what scripts used to yield for doing nothing before the action set existed.
+            -1 | ACTION_WAIT => Self::Wait,
+            ACTION_EAT => Self::Eat,
+            ACTION_PICK_UP => Self::PickUp,
+            ACTION_DROP => Self::Drop,
+            ACTION_ENTER_HOLE => Self::EnterHole,
+            ACTION_LEAVE_HOLE => Self::LeaveHole,
+            ACTION_DIG => Self::Dig,
+            11..=14 => Self::Move((code - DIAGONAL_OFFSET) as u8),
+            _ => return None,
+        })
+    }
+
+    /// The code a script would yield for the action.
+    pub(crate) fn code(&self) -> i64 {
+        match self {
+            Self::Move(direction) => move_code(*direction),
+            Self::Wait => ACTION_WAIT,
+            Self::Eat => ACTION_EAT,
+            Self::PickUp => ACTION_PICK_UP,
+            Self::Drop => ACTION_DROP,
+            Self::EnterHole => ACTION_ENTER_HOLE,
+            Self::LeaveHole => ACTION_LEAVE_HOLE,
+            Self::Dig => ACTION_DIG,
+        }
+    }
+
+    /// Number of ticks the action takes. The raccoon's script is not resumed until it finishes.
+    pub(crate) fn duration(&self) -> u32 {
+        match self {
+            Self::Move(_) | Self::Wait | Self::PickUp | Self::Drop => 1,
+            Self::EnterHole | Self::LeaveHole => 1,
+            Self::Eat => 2,
+            Self::Dig => DIG_TICKS,
+        }
+    }
+
+    /// Satiety spent on top of the regular hunger when the action succeeds.
+    pub(crate) fn cost(&self) -> f32 {
+        match self {
+            Self::Wait | Self::Eat | Self::Drop | Self::EnterHole | Self::LeaveHole => 0.,
+            Self::Move(_) | Self::PickUp => 0.001,
+            Self::Dig => 0.1,
+        }
+    }
+}
+
+/// The outcome of the last action a script requested, as returned by `last_action_result()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ActionResult {
+    /// The script did not yield an action.
+    None = 0,
+    Done = 1,
+    BlockedByWall = 2,
+    BlockedByRaccoon = 3,
+    OutOfBounds = 4,
+    /// The action code was unknown or its preconditions did not hold.
+    Invalid = 5,
+}
+
+/// Names of the action results, exposed to scripts as constant functions like the actions.
+pub(crate) const RESULT_CONSTANTS: [(&str, ActionResult); 6] = [
+    ("NO_ACTION", ActionResult::None),
+    ("DONE", ActionResult::Done),
+    ("BLOCKED_BY_WALL", ActionResult::BlockedByWall),
+    ("BLOCKED_BY_RACCOON", ActionResult::BlockedByRaccoon),
+    ("OUT_OF_BOUNDS", ActionResult::OutOfBounds),
+    ("INVALID_ACTION", ActionResult::Invalid),
+];
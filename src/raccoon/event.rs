@@ -0,0 +1,58 @@
+//! Events that the host reports to raccoon scripts.
+//!
+//! A script can define a handler function for any of the events, like `fn on_eat() -> i64`. The
+//! host calls the handler before resuming `main` in the raccoon's next turn, and `main` carries on
+//! where it left off once the handler returns. Events without a handler are dropped.
+
+use std::collections::VecDeque;
+
+use ruscal::value::Value;
+
+/// Satiety below which a raccoon gets an `on_hungry` event.
+pub(crate) const HUNGRY_SATIETY: f32 = 0.3;
+
+/// Events that have not been handled yet are dropped beyond this many, oldest first, so that a
+/// busy raccoon does not fall behind forever.
+const MAX_PENDING_EVENTS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Event {
+    /// The raccoon ate a corn.
+    Eat,
+    /// A move was blocked, with the action code of the move.
+    Blocked(i64),
+    /// Satiety fell below `HUNGRY_SATIETY`.
+    Hungry,
+    EnterHole,
+    /// A raccoon of the same team broadcast a value.
+    Message(i64),
+}
+
+impl Event {
+    /// The name of the script function that handles the event.
+    pub(crate) fn handler(&self) -> &'static str {
+        match self {
+            Self::Eat => "on_eat",
+            Self::Blocked(_) => "on_blocked",
+            Self::Hungry => "on_hungry",
+            Self::EnterHole => "on_enter_hole",
+            Self::Message(_) => "on_message",
+        }
+    }
+
+    pub(crate) fn args(&self) -> Vec<Value> {
+        match self {
+            Self::Blocked(direction) => vec![Value::I64(*direction)],
+            Self::Message(value) => vec![Value::I64(*value)],
+            Self::Eat | Self::Hungry | Self::EnterHole => vec![],
+        }
+    }
+}
+
+/// Queue an event for the raccoon's script.
+pub(crate) fn notify(events: &mut VecDeque<Event>, event: Event) {
+    if MAX_PENDING_EVENTS <= events.len() {
+        events.pop_front();
+    }
+    events.push_back(event);
+}
@@ -0,0 +1,176 @@
+//! Pre-emption of scripts that do not yield.
+//!
+//! The VM runs a coroutine until it yields, with no way to interrupt it from the outside. So
+//! before a script is compiled, a checkpoint is inserted at the start of every loop body and every
+//! function body:
+//!
+//! ```text
+//! for i in 0 to 10 { if step_checkpoint(1, 18) { yield 0; }; ...
+//! ```
+//!
+//! `step_checkpoint()` counts as a step like any native call, and returns 1 once the script has
+//! used up its step budget for the tick. The script then yields in the middle of its loop, and the
+//! host knows from the flag the checkpoint set that the yield was not an action. The coroutine is
+//! resumed in the next tick right where it stopped. Since no loop or recursion can run without
+//! passing a checkpoint, the code between two steps is bounded by the length of the script.
+//!
+//! The checkpoint goes on the line of the opening brace, so the line numbers of errors stay the
+//! same. It passes the line and column of the brace, since the VM does not tell where an error
+//! happened: the last checkpoint a coroutine passed is the block it was running.
+
+/// The native function that every checkpoint calls. Both raccoon and director scripts define it.
+pub(crate) const CHECKPOINT_FN: &str = "step_checkpoint";
+
+/// Where in the source a checkpoint is, counted from 1 like in compile errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The statement inserted after the opening brace of loops and functions at `location`.
+fn checkpoint(location: Location) -> String {
+    format!(
+        " if {CHECKPOINT_FN}({}, {}) {{ yield 0; }};",
+        location.line, location.column
+    )
+}
+
+/// Insert a checkpoint at the start of the body of every `for`, `while` and `fn` in `source`.
+/// Strings and comments are left alone.
+pub(crate) fn instrument(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.char_indices().peekable();
+    // Whether a keyword was seen whose body starts at the next opening brace.
+    let mut pending = false;
+    let mut lines = LineCounter::default();
+    while let Some((i, c)) = chars.next() {
+        out.push(c);
+        match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next().map(|(_, c)| c)),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    out.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                out.extend(chars.next().map(|(_, c)| c));
+                let mut prev = ' ';
+                for (_, c) in chars.by_ref() {
+                    out.push(c);
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            '{' if pending => {
+                out.push_str(&checkpoint(lines.location(source, i)));
+                pending = false;
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if !c.is_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                out.push_str(&word[c.len_utf8()..]);
+                if matches!(word.as_str(), "for" | "while" | "fn") {
+                    pending = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Finds the locations of offsets into a source, in increasing order, without counting the lines
+/// from the start each time.
+#[derive(Default)]
+struct LineCounter {
+    /// The offset up to which the lines have been counted.
+    counted: usize,
+    /// The number of lines before `counted`, less one.
+    newlines: usize,
+    /// The offset where the line of `counted` starts.
+    line_start: usize,
+}
+
+impl LineCounter {
+    fn location(&mut self, source: &str, offset: usize) -> Location {
+        for (i, c) in source[self.counted..offset].char_indices() {
+            if c == '\n' {
+                self.newlines += 1;
+                self.line_start = self.counted + i + 1;
+            }
+        }
+        self.counted = offset;
+        Location {
+            line: self.newlines + 1,
+            column: source[self.line_start..offset].chars().count() + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(line: usize, column: usize) -> String {
+        checkpoint(Location { line, column })
+    }
+
+    #[test]
+    fn loops_and_functions() {
+        let source = "fn f() -> i64 {\n    return 1;\n}\nfor i in 0 to 10 {\n    f();\n}\n";
+        let expected = format!(
+            "fn f() -> i64 {{{}\n    return 1;\n}}\nfor i in 0 to 10 {{{}\n    f();\n}}\n",
+            at(1, 15),
+            at(4, 18)
+        );
+        assert_eq!(instrument(source), expected);
+    }
+
+    #[test]
+    fn lines_stay_the_same() {
+        let source = "for i in 0 to 10 {\n    if i < 5 {\n        yield 0;\n    };\n}\n";
+        assert_eq!(instrument(source).lines().count(), source.lines().count());
+    }
+
+    #[test]
+    fn strings_comments_and_identifiers() {
+        let source = "print(\"for {\"); // fn {\n/* while { */ format_for(before); { }";
+        assert_eq!(instrument(source), source);
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let source = "x;\n/* é { */ while x {\n}";
+        assert_eq!(
+            instrument(source),
+            format!("x;\n/* é {{ */ while x {{{}\n}}", at(2, 19))
+        );
+    }
+}
@@ -1,65 +1,129 @@
 use eframe::{
     egui::Painter,
     emath::{Align2, RectTransform},
-    epaint::{vec2, Color32, FontId, PathShape, Pos2, Rect, TextureHandle, Vec2},
+    epaint::{pos2, vec2, Color32, FontId, PathShape, Pos2, Rect},
 };
 
-use super::Raccoon;
-use crate::app::CELL_SIZE_F;
+use super::{AgentKind, Raccoon};
+use crate::{app::Sprite, world::CELL_SIZE_F};
+
+/// Agents further apart than this between ticks were placed rather than walked, so they are not
+/// interpolated.
+const MAX_STEP: f32 = 1.5;
 
 impl Raccoon {
+    /// Draw the agent with a sprite sheet of square frames laid out horizontally. The first frame
+    /// is standing still and the rest are the walk cycle, facing right.
+    ///
+    /// `t` is the fraction of the tick that has passed, which is used to slide the sprite from
+    /// its previous position over all the ticks the move takes.
     pub fn render(
         &self,
         painter: &Painter,
-        texture: &TextureHandle,
-        size: Vec2,
+        sprite: &Sprite,
         to_screen: &RectTransform,
         font: FontId,
+        t: f32,
     ) {
         let state = self.state.borrow();
-        let min = state.pos.to_vec2() * CELL_SIZE_F;
-        let max = min + size;
+        let moving = state.prev_pos != state.pos;
+        let pos = if (state.pos - state.prev_pos).length() < MAX_STEP {
+            let ticks = state.move_ticks.max(1);
+            let elapsed = ticks.saturating_sub(state.busy + 1);
+            state
+                .prev_pos
+                .lerp(state.pos, (elapsed as f32 + t) / ticks as f32)
+        } else {
+            state.pos
+        };
+
+        let size = sprite.size;
+        let frames = (size.x / size.y).round().max(1.) as usize;
+        let frame = if moving && 1 < frames {
+            1 + state.walk_step % (frames - 1)
+        } else {
+            0
+        };
+        let (u0, u1) = (
+            frame as f32 / frames as f32,
+            (frame + 1) as f32 / frames as f32,
+        );
+        let uv = if state.facing_left {
+            Rect::from_min_max(pos2(u1, 0.), pos2(u0, 1.))
+        } else {
+            Rect::from_min_max(pos2(u0, 0.), pos2(u1, 1.))
+        };
+
+        let min = pos.to_vec2() * CELL_SIZE_F;
+        let max = min + vec2(size.y, size.y);
         let rect = Rect {
             min: min.to_pos2(),
             max: max.to_pos2(),
         };
-        const UV: Rect = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
-        painter.image(texture.id(), to_screen.transform_rect(rect), UV, state.tint);
+        sprite.paint(painter, to_screen.transform_rect(rect), uv, state.tint);
 
-        let bar_bg = Rect::from_min_size(
-            (state.pos.to_vec2() * CELL_SIZE_F).to_pos2(),
-            vec2(CELL_SIZE_F, 10.),
-        );
-        painter.rect_filled(
-            to_screen.transform_rect(bar_bg),
-            0.,
-            Color32::from_rgb(31, 31, 31),
-        );
-        let bar_rect = Rect::from_min_size(
-            (state.pos.to_vec2() * CELL_SIZE_F).to_pos2(),
-            vec2(state.satiety * CELL_SIZE_F, 10.),
-        );
-        let bar_color = if state.satiety < 0.3 {
-            Color32::RED
-        } else if state.satiety < 0.6 {
-            Color32::YELLOW
+        if self.kind == AgentKind::Predator {
+            painter.text(
+                to_screen.transform_pos(rect.min),
+                Align2::CENTER_TOP,
+                state.caught,
+                font.clone(),
+                Color32::RED,
+            );
         } else {
-            Color32::from_rgb(31, 255, 31)
-        };
-        painter.rect_filled(to_screen.transform_rect(bar_rect), 0., bar_color);
+            let bar_bg = Rect::from_min_size(rect.min, vec2(CELL_SIZE_F, 10.));
+            painter.rect_filled(
+                to_screen.transform_rect(bar_bg),
+                0.,
+                Color32::from_rgb(31, 31, 31),
+            );
+            let bar_rect = Rect::from_min_size(rect.min, vec2(state.satiety * CELL_SIZE_F, 10.));
+            let bar_color = if state.satiety < 0.3 {
+                Color32::RED
+            } else if state.satiety < 0.6 {
+                Color32::YELLOW
+            } else {
+                Color32::from_rgb(31, 255, 31)
+            };
+            painter.rect_filled(to_screen.transform_rect(bar_rect), 0., bar_color);
 
-        painter.text(
-            to_screen.transform_pos(rect.min),
-            Align2::CENTER_TOP,
-            state.ate,
-            font.clone(),
-            Color32::WHITE,
-        );
+            painter.text(
+                to_screen.transform_pos(rect.min),
+                Align2::CENTER_TOP,
+                state.ate,
+                font.clone(),
+                Color32::WHITE,
+            );
+        }
+
+        if 0 < state.carrying {
+            painter.text(
+                to_screen.transform_pos(rect.right_top()),
+                Align2::RIGHT_TOP,
+                format!("+{}", state.carrying),
+                font.clone(),
+                Color32::YELLOW,
+            );
+        }
+
+        if state.misbehaving {
+            painter.rect_stroke(to_screen.transform_rect(rect), 0., (3., Color32::RED));
+            painter.text(
+                to_screen.transform_pos(rect.center_bottom()),
+                Align2::CENTER_BOTTOM,
+                format!("pre-empted {}", state.preempted),
+                font.clone(),
+                Color32::RED,
+            );
+        }
 
         if let Some(path) = &state.path {
+            // Start the line from where the sprite is drawn, not the cell it has already reached.
             let plot: Vec<_> = path
                 .iter()
-                .map(|node| to_screen.transform_pos(Pos2::from(node)))
+                .map(Pos2::from)
+                .chain(std::iter::once(rect.center()))
+                .map(|pos| to_screen.transform_pos(pos))
                 .collect();
             painter.add(PathShape::line(plot, (3., state.tint)));
         }
@@ -0,0 +1,133 @@
+//! Tasks: coroutines of a raccoon's script that run side by side, such as one that moves the
+//! raccoon around and one that keeps an eye on its surroundings.
+//!
+//! `main` is the first task, with priority 0. A script starts more with
+//! `spawn_task("function", priority)`, which runs a function without arguments as a task of its
+//! own from the next tick on. In every turn of the raccoon, the tasks are resumed in order of
+//! priority, highest first, until one of them yields an action other than `WAIT()`. That action
+//! is the raccoon's for the turn, and the tasks after it stay suspended, so only one task moves
+//! the raccoon at a time.
+//!
+//! When `main` runs to its end or fails, the raccoon's `RestartPolicy` decides what comes next.
+
+use ruscal::vm::Vm;
+
+use super::preempt::Location;
+
+/// The id of the task running `main`, which cannot be stopped.
+pub(crate) const MAIN_TASK: usize = 0;
+
+/// Most tasks a raccoon can run at once, including `main`.
+pub(crate) const MAX_TASKS: usize = 8;
+
+pub(super) struct Task {
+    pub id: usize,
+    /// The script function the task runs.
+    pub function: String,
+    pub priority: i64,
+    pub vm: Vm,
+    /// The checkpoint the task passed last.
+    pub location: Option<Location>,
+}
+
+/// What a raccoon does once `main` has run to its end or failed with an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum RestartPolicy {
+    /// Start `main` over in the next turn.
+    #[default]
+    Restart,
+    /// Stop running the script, so that the raccoon idles for the rest of its life.
+    Halt,
+    /// The raccoon dies.
+    Die,
+}
+
+impl RestartPolicy {
+    pub(crate) const ALL: [Self; 3] = [Self::Restart, Self::Halt, Self::Die];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Restart => "restart",
+            Self::Halt => "halt",
+            Self::Die => "die",
+        }
+    }
+}
+
+impl std::str::FromStr for RestartPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name() == s)
+            .ok_or_else(|| format!("unknown policy `{s}`, expected restart, halt or die"))
+    }
+}
+
+/// Errors kept in the log of a raccoon, beyond which the oldest are dropped.
+pub(crate) const MAX_LOGGED_ERRORS: usize = 32;
+
+/// An error of a raccoon's script, as kept in its log.
+pub(crate) struct ScriptError {
+    /// The tick the error happened in.
+    pub tick: usize,
+    /// The script function that failed: a task's function or an event handler.
+    pub function: String,
+    /// Where the loop or function body that failed starts, if the script got that far.
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tick {}, in {}", self.tick, self.function)?;
+        if let Some(location) = self.location {
+            write!(f, ", in the block at {location}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// A change to the tasks that a script asked for, applied when the scheduler regains control.
+pub(crate) enum TaskRequest {
+    Spawn {
+        id: usize,
+        function: String,
+        priority: i64,
+    },
+    Stop(usize),
+}
+
+/// Where a task of `priority` goes among tasks of `priorities`, which are in decreasing order:
+/// after the tasks of the same priority, so that those started earlier run first.
+pub(super) fn insertion_index(
+    mut priorities: impl ExactSizeIterator<Item = i64>,
+    priority: i64,
+) -> usize {
+    let len = priorities.len();
+    priorities.position(|other| other < priority).unwrap_or(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(priorities: &[i64], priority: i64) -> usize {
+        insertion_index(priorities.iter().copied(), priority)
+    }
+
+    #[test]
+    fn ordered_by_priority() {
+        assert_eq!(index(&[], 3), 0);
+        assert_eq!(index(&[5, 2, -1], 7), 0);
+        assert_eq!(index(&[5, 2, -1], 3), 1);
+        assert_eq!(index(&[5, 2, -1], -4), 3);
+    }
+
+    #[test]
+    fn after_the_same_priority() {
+        assert_eq!(index(&[5, 2, 2, 0], 2), 3);
+        assert_eq!(index(&[1, 1], 1), 2);
+    }
+}
@@ -0,0 +1,549 @@
+//! Resolution of the moves that agents want to make in a tick.
+//!
+//! Every agent first decides where to go in `Raccoon::animate`, and the moves are settled here
+//! all at once, so the outcome does not depend on the order the agents are animated in.
+
+use std::collections::HashMap;
+
+use eframe::epaint::{pos2, Pos2};
+
+use super::{
+    action::{move_code, Action, ActionResult},
+    event::{notify, Event},
+    AgentKind, Raccoon,
+};
+use crate::{
+    pathfinding::{step_cost, Path, DIRECTIONS, STRAIGHT_COST},
+    world::{MapCell, BOARD_SIZE},
+};
+
+/// Number of ticks in a row an agent can fail to move before it is reported as stuck.
+pub(crate) const STUCK_TICKS: usize = 10;
+
+/// Number of steps ahead on its path an agent reserves for cooperative planning.
+const RESERVATION_DEPTH: usize = 3;
+
+/// How agents get out of each other's way, shared by all agents in the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TrafficRules {
+    /// Whether two agents facing each other can squeeze past by swapping their cells.
+    pub allow_swaps: bool,
+    /// Whether path searches avoid the cells that other agents have reserved.
+    pub cooperative: bool,
+}
+
+impl Default for TrafficRules {
+    fn default() -> Self {
+        Self {
+            allow_swaps: true,
+            cooperative: false,
+        }
+    }
+}
+
+/// Cells that agents stand on or are about to walk through, with the id of the agent.
+pub(crate) type Reservations = HashMap<[i32; 2], usize>;
+
+/// Replace the reservations of agent `id` with its current cell and the next few steps of its
+/// path.
+pub(crate) fn reserve(reservations: &mut Reservations, id: usize, pos: Pos2, path: Option<&Path>) {
+    reservations.retain(|_, owner| *owner != id);
+    reservations.insert([pos.x as i32, pos.y as i32], id);
+    // The path is reversed and its last node is the current cell.
+    for node in path.into_iter().flatten().rev().take(RESERVATION_DEPTH + 1) {
+        reservations.entry(node.pos).or_insert(id);
+    }
+}
+
+/// Rebuild the reservation table from scratch at the start of a tick.
+pub(crate) fn reserve_all(reservations: &mut Reservations, agents: &[Raccoon]) {
+    reservations.clear();
+    for agent in agents {
+        let state = agent.state.borrow();
+        reserve(reservations, agent.id, state.pos, state.path.as_ref());
+    }
+}
+
+/// A copy of the map where the cells reserved by other agents are walls, so that a search routes
+/// around them. The goals are left open, since we would never reach them otherwise.
+pub(crate) fn avoiding_map(
+    map: &[MapCell],
+    reservations: &Reservations,
+    id: usize,
+    goals: &[Pos2],
+) -> Vec<MapCell> {
+    let mut map = map.to_vec();
+    for (cell, owner) in reservations {
+        if *owner == id
+            || goals
+                .iter()
+                .any(|goal| goal.x as i32 == cell[0] && goal.y as i32 == cell[1])
+        {
+            continue;
+        }
+        map[cell[0] as usize + cell[1] as usize * BOARD_SIZE] = MapCell::Wall;
+    }
+    map
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Unknown,
+    Move,
+    Blocked,
+}
+
+/// What stands on a cell at the start of the tick, as far as settling the moves is concerned.
+#[derive(Debug, Clone, Copy)]
+struct Body {
+    pos: Pos2,
+    role: Role,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Predator,
+    /// A raccoon out in the open, which predators catch.
+    Prey,
+    /// A raccoon in a hole, out of the predators' reach.
+    Hidden,
+}
+
+/// How the moves of a tick turned out.
+struct Settled {
+    /// The outcome of each mover.
+    outcomes: Vec<Outcome>,
+    /// The agents that were caught, as indices of the predator and of the prey.
+    catches: Vec<(usize, usize)>,
+}
+
+struct Mover {
+    /// Index into the agents.
+    agent: usize,
+    from: Pos2,
+    to: Pos2,
+    /// Who gets a contested cell: the lowest value wins.
+    priority: (std::cmp::Reverse<usize>, usize),
+}
+
+/// Settle the moves that agents decided on in this tick.
+///
+/// 1. A predator catches a raccoon outside a hole where they meet: when it steps towards the cell
+///    the raccoon ends up in, when the raccoon steps into the predator's cell, or when they pass
+///    through each other. A caught raccoon does not move, and the others move as if it was gone.
+/// 2. If several agents want the same cell, the one stuck the longest gets it, with ties going
+///    to the lowest id.
+/// 3. An agent can step into a cell that is being vacated in the same tick, so trains of agents
+///    move together, and so do cycles of agents following each other. Swaps are cycles of two,
+///    which are only allowed if the traffic rules say so. The same goes for two diagonal steps
+///    that cross each other.
+pub(crate) fn resolve_moves(agents: &[Raccoon], map: &[MapCell], rules: TrafficRules) {
+    let movers: Vec<Mover> = agents
+        .iter()
+        .enumerate()
+        .filter_map(|(agent, raccoon)| {
+            let mut state = raccoon.state.borrow_mut();
+            let to = state.planned_move.take()?;
+            let priority = (std::cmp::Reverse(state.stuck), raccoon.id);
+            (!state.dead).then_some(Mover {
+                agent,
+                from: state.pos,
+                to,
+                priority,
+            })
+        })
+        .collect();
+
+    let bodies: Vec<Option<Body>> = agents
+        .iter()
+        .map(|agent| {
+            let state = agent.state.borrow();
+            let role = match agent.kind {
+                AgentKind::Predator => Role::Predator,
+                AgentKind::Raccoon if state.in_hole => Role::Hidden,
+                AgentKind::Raccoon => Role::Prey,
+            };
+            (!state.dead).then_some(Body {
+                pos: state.pos,
+                role,
+            })
+        })
+        .collect();
+
+    let Settled { outcomes, catches } = settle(&movers, &bodies, rules);
+    for (predator, prey) in catches {
+        let (predator, prey) = (&agents[predator], &agents[prey]);
+        let mut state = prey.state.borrow_mut();
+        state.dead = true;
+        state.cause_of_death = Some("predator");
+        drop(state);
+        predator.state.borrow_mut().caught += 1;
+        log_event!("Raccoon {} was caught by predator {}", prey.id, predator.id);
+    }
+    for (mover, outcome) in movers.iter().zip(outcomes) {
+        let agent = &agents[mover.agent];
+        let mut state = agent.state.borrow_mut();
+        if state.dead {
+            continue;
+        }
+        if outcome == Outcome::Move {
+            // Rough terrain keeps us busy for a while after the step, and so does the longer
+            // way of a diagonal step, timed in the same units the path finder plans with.
+            let from = [state.pos.x as i32, state.pos.y as i32];
+            let cost = step_cost(map, from, mover.to - state.pos).unwrap_or(STRAIGHT_COST) as u32
+                + state.walk_carry;
+            let straight = STRAIGHT_COST as u32;
+            state.walk_carry = cost % straight;
+            if mover.to.x != state.pos.x {
+                state.facing_left = mover.to.x < state.pos.x;
+            }
+            state.walk_step += 1;
+            state.pos = mover.to;
+            state.busy = cost / straight - 1;
+            state.move_ticks = state.busy + 1;
+            state.satiety -= Action::Move(0).cost();
+            state.last_action = ActionResult::Done;
+            state.stuck = 0;
+        } else {
+            state.last_action = ActionResult::BlockedByRaccoon;
+            let step = mover.to - state.pos;
+            if let Some(direction) = DIRECTIONS.iter().position(|direction| *direction == step) {
+                notify(
+                    &mut state.events,
+                    Event::Blocked(move_code(direction as u8)),
+                );
+            }
+            state.stuck += 1;
+            if state.stuck == STUCK_TICKS {
+                log_event!(
+                    "Agent {} is stuck at ({}, {}) for {} ticks",
+                    agent.id,
+                    state.pos.x,
+                    state.pos.y,
+                    state.stuck
+                );
+            }
+        }
+    }
+}
+
+/// Settle the moves of `movers` among `bodies`, the agents by index or `None` for those that
+/// are dead.
+fn settle(movers: &[Mover], bodies: &[Option<Body>], rules: TrafficRules) -> Settled {
+    let outcomes = settle_moves(movers, bodies, rules);
+    let catches = catches(movers, &outcomes, bodies);
+    if catches.is_empty() {
+        return Settled { outcomes, catches };
+    }
+    // The others move as if the caught raccoons had never been there.
+    let mut bodies = bodies.to_vec();
+    for (_, prey) in &catches {
+        bodies[*prey] = None;
+    }
+    Settled {
+        outcomes: settle_moves(movers, &bodies, rules),
+        catches,
+    }
+}
+
+/// Decide which of `movers` get to move. Movers without a body stay where they are.
+fn settle_moves(movers: &[Mover], bodies: &[Option<Body>], rules: TrafficRules) -> Vec<Outcome> {
+    let mut occupant = HashMap::new();
+    for (i, body) in bodies.iter().enumerate() {
+        if let Some(body) = body {
+            occupant.insert([body.pos.x as i32, body.pos.y as i32], i);
+        }
+    }
+
+    // Pick a single winner for every contested cell.
+    let mut outcomes = vec![Outcome::Unknown; movers.len()];
+    let mut winner_of: HashMap<[i32; 2], usize> = HashMap::new();
+    for (i, mover) in movers.iter().enumerate() {
+        if bodies[mover.agent].is_none() {
+            outcomes[i] = Outcome::Blocked;
+            continue;
+        }
+        let cell = [mover.to.x as i32, mover.to.y as i32];
+        match winner_of.get(&cell) {
+            Some(&rival) if movers[rival].priority < mover.priority => {
+                outcomes[i] = Outcome::Blocked;
+            }
+            Some(&rival) => {
+                outcomes[rival] = Outcome::Blocked;
+                winner_of.insert(cell, i);
+            }
+            None => {
+                winner_of.insert(cell, i);
+            }
+        }
+    }
+    if !rules.allow_swaps {
+        for (i, mover) in movers.iter().enumerate() {
+            for (j, other) in movers.iter().enumerate().skip(i + 1) {
+                if crosses(mover, other) {
+                    outcomes[i] = Outcome::Blocked;
+                    outcomes[j] = Outcome::Blocked;
+                }
+            }
+        }
+    }
+    let mover_of: HashMap<usize, usize> = movers
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| outcomes[*i] == Outcome::Unknown)
+        .map(|(i, mover)| (mover.agent, i))
+        .collect();
+
+    // Follow each winner to whoever stands in its way until we find a free cell, an agent that
+    // stays, or a cycle.
+    for start in 0..movers.len() {
+        let mut chain = vec![];
+        let mut cur = start;
+        let outcome = loop {
+            if outcomes[cur] != Outcome::Unknown {
+                break outcomes[cur];
+            }
+            if let Some(i) = chain.iter().position(|c| *c == cur) {
+                let outcome = if 2 < chain.len() - i || rules.allow_swaps {
+                    Outcome::Move
+                } else {
+                    Outcome::Blocked
+                };
+                for c in chain.drain(i..) {
+                    outcomes[c] = outcome;
+                }
+                break outcome;
+            }
+            chain.push(cur);
+            let to = movers[cur].to;
+            let Some(agent) = occupant.get(&[to.x as i32, to.y as i32]) else {
+                break Outcome::Move;
+            };
+            let Some(next) = mover_of.get(agent) else {
+                break Outcome::Blocked;
+            };
+            cur = *next;
+        };
+        for c in chain {
+            outcomes[c] = outcome;
+        }
+    }
+
+    outcomes
+}
+
+/// The predators and the raccoons they catch, once the moves are settled: a predator catches a
+/// raccoon in the open if it steps towards the cell the raccoon ends up in, if the raccoon steps
+/// into the cell the predator ends up in, or if they pass through each other.
+fn catches(movers: &[Mover], outcomes: &[Outcome], bodies: &[Option<Body>]) -> Vec<(usize, usize)> {
+    let mut target: Vec<Option<Pos2>> = bodies
+        .iter()
+        .map(|body| body.map(|body| body.pos))
+        .collect();
+    let mut end = target.clone();
+    let mut moved = vec![None; bodies.len()];
+    for (i, mover) in movers.iter().enumerate() {
+        if bodies[mover.agent].is_none() {
+            continue;
+        }
+        target[mover.agent] = Some(mover.to);
+        if outcomes[i] == Outcome::Move {
+            end[mover.agent] = Some(mover.to);
+            moved[mover.agent] = Some(mover);
+        }
+    }
+    let with_role = |role| {
+        bodies
+            .iter()
+            .enumerate()
+            .filter(move |(_, body)| body.map(|body| body.role) == Some(role))
+            .map(|(i, _)| i)
+    };
+
+    let mut catches = vec![];
+    for prey in with_role(Role::Prey) {
+        let predator = with_role(Role::Predator).find(|&predator| {
+            let passed = match (moved[predator], moved[prey]) {
+                (Some(a), Some(b)) => (a.from == b.to && a.to == b.from) || crosses(a, b),
+                _ => false,
+            };
+            target[predator] == end[prey] || target[prey] == end[predator] || passed
+        });
+        if let Some(predator) = predator {
+            catches.push((predator, prey));
+        }
+    }
+    catches
+}
+
+/// Whether two diagonal steps cross each other in the middle of the square they share.
+fn crosses(a: &Mover, b: &Mover) -> bool {
+    let corners = [pos2(a.to.x, a.from.y), pos2(a.from.x, a.to.y)];
+    a.from.x != a.to.x
+        && a.from.y != a.to.y
+        && (b.from == corners[0] && b.to == corners[1]
+            || b.from == corners[1] && b.to == corners[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::epaint::pos2;
+
+    /// Agent `agent` stepping from `from` to `to`, stuck for `stuck` ticks. Ids are the agent
+    /// indices.
+    fn mover(agent: usize, from: [i32; 2], to: [i32; 2], stuck: usize) -> Mover {
+        Mover {
+            agent,
+            from: pos2(from[0] as f32, from[1] as f32),
+            to: pos2(to[0] as f32, to[1] as f32),
+            priority: (std::cmp::Reverse(stuck), agent),
+        }
+    }
+
+    /// Agents with `roles` standing on `cells`, in order.
+    fn bodies_with(cells: &[[i32; 2]], roles: &[Role]) -> Vec<Option<Body>> {
+        cells
+            .iter()
+            .zip(roles)
+            .map(|(cell, role)| {
+                Some(Body {
+                    pos: pos2(cell[0] as f32, cell[1] as f32),
+                    role: *role,
+                })
+            })
+            .collect()
+    }
+
+    /// Raccoons standing on `cells`, in order.
+    fn bodies(cells: &[[i32; 2]]) -> Vec<Option<Body>> {
+        bodies_with(cells, &vec![Role::Prey; cells.len()])
+    }
+
+    const SWAPS: TrafficRules = TrafficRules {
+        allow_swaps: true,
+        cooperative: false,
+    };
+    const NO_SWAPS: TrafficRules = TrafficRules {
+        allow_swaps: false,
+        cooperative: false,
+    };
+
+    #[test]
+    fn free_cell() {
+        let settled = settle(&[mover(0, [0, 0], [1, 0], 0)], &bodies(&[[0, 0]]), SWAPS);
+        assert_eq!(settled.outcomes, [Outcome::Move]);
+    }
+
+    #[test]
+    fn contested_cell_goes_to_the_most_stuck() {
+        let movers = [mover(0, [0, 0], [1, 0], 0), mover(1, [2, 0], [1, 0], 3)];
+        let settled = settle(&movers, &bodies(&[[0, 0], [2, 0]]), SWAPS);
+        assert_eq!(settled.outcomes, [Outcome::Blocked, Outcome::Move]);
+
+        // Ties go to the lowest id.
+        let movers = [mover(1, [2, 0], [1, 0], 0), mover(0, [0, 0], [1, 0], 0)];
+        let settled = settle(&movers, &bodies(&[[0, 0], [2, 0]]), SWAPS);
+        assert_eq!(settled.outcomes, [Outcome::Blocked, Outcome::Move]);
+    }
+
+    #[test]
+    fn trains_move_together() {
+        let movers = [
+            mover(0, [0, 0], [1, 0], 0),
+            mover(1, [1, 0], [2, 0], 0),
+            mover(2, [2, 0], [3, 0], 0),
+        ];
+        let settled = settle(&movers, &bodies(&[[0, 0], [1, 0], [2, 0]]), SWAPS);
+        assert_eq!(settled.outcomes, [Outcome::Move; 3]);
+    }
+
+    #[test]
+    fn train_behind_an_agent_that_stays() {
+        let movers = [mover(0, [0, 0], [1, 0], 0), mover(1, [1, 0], [2, 0], 0)];
+        let settled = settle(&movers, &bodies(&[[0, 0], [1, 0], [2, 0]]), SWAPS);
+        assert_eq!(settled.outcomes, [Outcome::Blocked; 2]);
+    }
+
+    #[test]
+    fn cycles_move_but_swaps_need_the_rule() {
+        let cells = bodies(&[[0, 0], [1, 0], [1, 1], [0, 1]]);
+        let movers = [
+            mover(0, [0, 0], [1, 0], 0),
+            mover(1, [1, 0], [1, 1], 0),
+            mover(2, [1, 1], [0, 1], 0),
+            mover(3, [0, 1], [0, 0], 0),
+        ];
+        assert_eq!(
+            settle(&movers, &cells, NO_SWAPS).outcomes,
+            [Outcome::Move; 4]
+        );
+
+        let movers = [mover(0, [0, 0], [1, 0], 0), mover(1, [1, 0], [0, 0], 0)];
+        let cells = bodies(&[[0, 0], [1, 0]]);
+        assert_eq!(settle(&movers, &cells, SWAPS).outcomes, [Outcome::Move; 2]);
+        assert_eq!(
+            settle(&movers, &cells, NO_SWAPS).outcomes,
+            [Outcome::Blocked; 2]
+        );
+    }
+
+    #[test]
+    fn crossing_diagonals_are_swaps() {
+        let movers = [mover(0, [0, 0], [1, 1], 0), mover(1, [1, 0], [0, 1], 0)];
+        let cells = bodies(&[[0, 0], [1, 0]]);
+        assert_eq!(settle(&movers, &cells, SWAPS).outcomes, [Outcome::Move; 2]);
+        assert_eq!(
+            settle(&movers, &cells, NO_SWAPS).outcomes,
+            [Outcome::Blocked; 2]
+        );
+
+        // Diagonals that only touch at a corner do not cross.
+        let movers = [mover(0, [0, 0], [1, 1], 0), mover(1, [2, 0], [1, -1], 0)];
+        let cells = bodies(&[[0, 0], [2, 0]]);
+        assert_eq!(
+            settle(&movers, &cells, NO_SWAPS).outcomes,
+            [Outcome::Move; 2]
+        );
+    }
+
+    #[test]
+    fn prey_that_gets_away_is_not_caught() {
+        let cells = bodies_with(&[[0, 0], [1, 0]], &[Role::Predator, Role::Prey]);
+        let movers = [mover(0, [0, 0], [1, 0], 0), mover(1, [1, 0], [2, 0], 0)];
+        let settled = settle(&movers, &cells, SWAPS);
+        assert!(settled.catches.is_empty());
+        assert_eq!(settled.outcomes, [Outcome::Move; 2]);
+
+        // A raccoon that stays is caught, and the predator takes its cell.
+        let settled = settle(&movers[..1], &cells, SWAPS);
+        assert_eq!(settled.catches, [(0, 1)]);
+        assert_eq!(settled.outcomes, [Outcome::Move]);
+
+        // So is one in the way of a predator that cannot get to it, but not one in a hole.
+        let cells = bodies_with(
+            &[[0, 0], [1, 0], [2, 0]],
+            &[Role::Predator, Role::Predator, Role::Prey],
+        );
+        let movers = [mover(0, [0, 0], [1, 0], 0), mover(1, [1, 0], [2, 0], 0)];
+        assert_eq!(settle(&movers, &cells, SWAPS).catches, [(1, 2)]);
+        let cells = bodies_with(&[[0, 0], [1, 0]], &[Role::Predator, Role::Hidden]);
+        let settled = settle(&movers[..1], &cells, SWAPS);
+        assert!(settled.catches.is_empty());
+        assert_eq!(settled.outcomes, [Outcome::Blocked]);
+    }
+
+    #[test]
+    fn prey_walking_into_a_predator_is_caught() {
+        let cells = bodies_with(&[[0, 0], [1, 0]], &[Role::Predator, Role::Prey]);
+        let settled = settle(&[mover(1, [1, 0], [0, 0], 0)], &cells, SWAPS);
+        assert_eq!(settled.catches, [(0, 1)]);
+
+        // Passing each other counts, whether the rules let them swap or not.
+        let movers = [mover(0, [0, 0], [1, 0], 0), mover(1, [1, 0], [0, 0], 0)];
+        assert_eq!(settle(&movers, &cells, SWAPS).catches, [(0, 1)]);
+        assert_eq!(settle(&movers, &cells, NO_SWAPS).catches, [(0, 1)]);
+        let movers = [mover(0, [0, 0], [1, 1], 0), mover(1, [1, 0], [0, 1], 0)];
+        assert_eq!(settle(&movers, &cells, SWAPS).catches, [(0, 1)]);
+    }
+}
@@ -0,0 +1,494 @@
+//! Scenarios: a map, the scripts that play on it and the objectives that decide whether they
+//! succeeded, in a file that level designers can write without touching the code.
+//!
+//! See `scenarios/stock_up.ini` for an example of the format.
+
+use std::path::Path;
+
+use eframe::epaint::{pos2, Pos2};
+
+use crate::{
+    ini,
+    raccoon::{LifeCycle, RestartPolicy},
+    world::{MapCell, World, BOARD_SIZE},
+};
+
+/// The map of a scenario and where things start on it.
+pub(crate) struct ScenarioMap {
+    pub cells: Vec<MapCell>,
+    pub holes: Vec<Pos2>,
+    pub corn: Vec<Pos2>,
+    /// Where raccoons start, in the order they are spawned. The rest start at random.
+    pub raccoon_spawns: Vec<Pos2>,
+    pub predator_spawns: Vec<Pos2>,
+}
+
+pub(crate) struct Scenario {
+    pub name: String,
+    pub description: String,
+    /// Ticks until the objectives are judged.
+    pub time_limit: usize,
+    pub seed: Option<u64>,
+    /// What the raccoons do when their script ends or fails, if not the default.
+    pub restart_policy: Option<RestartPolicy>,
+    /// Steps per tick of the scripts, if not the default.
+    pub step_budget: Option<usize>,
+    /// The map, or `None` to generate one.
+    pub map: Option<ScenarioMap>,
+    /// Paths of the scripts, one per team.
+    pub scripts: Vec<String>,
+    /// Path of the world script that stages events, if any.
+    pub director: Option<String>,
+    /// Overrides of the colony parameters, as names of `LifeCycle::PARAMS` and values.
+    pub life: Vec<(String, f64)>,
+    pub predators: usize,
+    /// Chance of a corn appearing in a tick.
+    pub corn_chance: f64,
+    /// No corn appears while there are this many on the ground.
+    pub corn_max: usize,
+    pub objectives: Vec<Objective>,
+}
+
+/// Something the raccoons have to achieve, or avoid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Objective {
+    /// Store this many corns in holes before the time limit.
+    Deliver(usize),
+    /// Eat this many corns before the time limit.
+    Eat(usize),
+    /// No raccoon may starve to death.
+    NoStarvation,
+    /// At least this many raccoons have to be alive all the time.
+    Population(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Status {
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+impl Status {
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl Objective {
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::Deliver(amount) => format!("Deliver {amount} corn"),
+            Self::Eat(amount) => format!("Eat {amount} corn"),
+            Self::NoStarvation => "No raccoon starves".to_string(),
+            Self::Population(count) => format!("Keep at least {count} raccoons alive"),
+        }
+    }
+
+    /// Judge the objective on the tally of the world. Goals to reach fail when the time runs out,
+    /// while conditions to keep succeed.
+    fn check(&self, tally: &Tally, time_up: bool) -> Status {
+        let (reached, broken) = match self {
+            Self::Deliver(amount) => (*amount <= tally.delivered, false),
+            Self::Eat(amount) => (*amount <= tally.eaten, false),
+            Self::NoStarvation => (false, tally.starved),
+            Self::Population(count) => (false, tally.population < *count),
+        };
+        let keep = matches!(self, Self::NoStarvation | Self::Population(_));
+        if broken {
+            Status::Failed
+        } else if reached {
+            Status::Succeeded
+        } else if time_up {
+            if keep {
+                Status::Succeeded
+            } else {
+                Status::Failed
+            }
+        } else {
+            Status::Pending
+        }
+    }
+}
+
+/// What the objectives are judged on.
+#[derive(Debug, Default)]
+struct Tally {
+    delivered: usize,
+    eaten: usize,
+    /// Whether any raccoon starved to death.
+    starved: bool,
+    population: usize,
+}
+
+impl Tally {
+    fn new(world: &World) -> Self {
+        let stats = world.team_stats();
+        Self {
+            delivered: stats.iter().map(|team| team.delivered).sum(),
+            eaten: stats.iter().map(|team| team.eaten).sum(),
+            starved: world
+                .departed
+                .iter()
+                .any(|departed| departed.state.borrow().cause_of_death == Some("starvation")),
+            population: world.population(),
+        }
+    }
+}
+
+/// How far a world has come with the objectives of its scenario.
+pub(crate) struct Progress {
+    pub statuses: Vec<Status>,
+}
+
+impl Progress {
+    pub(crate) fn new(scenario: &Scenario) -> Self {
+        Self {
+            statuses: vec![Status::Pending; scenario.objectives.len()],
+        }
+    }
+
+    /// Judge the objectives that are still pending, once per tick.
+    pub(crate) fn update(&mut self, scenario: &Scenario, world: &World) {
+        self.judge(
+            scenario,
+            &Tally::new(world),
+            scenario.time_limit <= world.ticks(),
+        );
+    }
+
+    fn judge(&mut self, scenario: &Scenario, tally: &Tally, time_up: bool) {
+        for (status, objective) in self.statuses.iter_mut().zip(&scenario.objectives) {
+            if *status == Status::Pending {
+                *status = objective.check(tally, time_up);
+            }
+        }
+    }
+
+    /// The scenario fails as soon as any objective fails, and succeeds once all of them have.
+    pub(crate) fn outcome(&self) -> Status {
+        if self.statuses.contains(&Status::Failed) {
+            Status::Failed
+        } else if self
+            .statuses
+            .iter()
+            .all(|status| *status == Status::Succeeded)
+        {
+            Status::Succeeded
+        } else {
+            Status::Pending
+        }
+    }
+}
+
+impl Scenario {
+    /// Load a scenario file. Script paths in it are relative to the file.
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let src = std::fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&src, dir).map_err(|e| format!("{}:{e}", path.display()))
+    }
+
+    pub(crate) fn parse(src: &str, dir: &Path) -> Result<Self, String> {
+        let mut scenario = Self {
+            name: String::new(),
+            description: String::new(),
+            time_limit: 0,
+            seed: None,
+            restart_policy: None,
+            step_budget: None,
+            map: None,
+            scripts: vec![],
+            director: None,
+            life: vec![],
+            predators: 1,
+            corn_chance: 0.1,
+            corn_max: 10,
+            objectives: vec![],
+        };
+        let mut time_limit = None;
+        let mut map_line = 0;
+        for section in ini::parse(src)? {
+            match section.name.as_str() {
+                "scenario" => {
+                    scenario.name = section.require("name")?.value.clone();
+                    if let Some(entry) = section.get("description") {
+                        scenario.description = entry.value.clone();
+                    }
+                    let entry = section.require("time_limit")?;
+                    let limit = entry.parse()?;
+                    if limit == 0 {
+                        return Err(format!("{}: the time limit has to be positive", entry.line));
+                    }
+                    time_limit = Some(limit);
+                    if let Some(entry) = section.get("seed") {
+                        scenario.seed = Some(entry.parse()?);
+                    }
+                    if let Some(entry) = section.get("on_script_end") {
+                        scenario.restart_policy = Some(entry.parse()?);
+                    }
+                    if let Some(entry) = section.get("step_budget") {
+                        scenario.step_budget = Some(entry.parse()?);
+                    }
+                }
+                "map" => {
+                    scenario.map = Some(parse_map(&section)?);
+                    map_line = section.line;
+                }
+                "team" => {
+                    let script = &section.require("script")?.value;
+                    scenario
+                        .scripts
+                        .push(dir.join(script).to_string_lossy().into_owned());
+                }
+                "director" => {
+                    let script = &section.require("script")?.value;
+                    scenario.director = Some(dir.join(script).to_string_lossy().into_owned());
+                }
+                "life" => {
+                    for entry in &section.entries {
+                        let value = entry.parse()?;
+                        LifeCycle::default()
+                            .set(&entry.key, value)
+                            .map_err(|e| format!("{}: {e}", entry.line))?;
+                        scenario.life.push((entry.key.clone(), value));
+                    }
+                }
+                "predators" => scenario.predators = section.require("count")?.parse()?,
+                "corn" => {
+                    if let Some(entry) = section.get("chance") {
+                        scenario.corn_chance = entry.parse()?;
+                        if !(0. ..=1.).contains(&scenario.corn_chance) {
+                            return Err(format!(
+                                "{}: the chance has to be between 0 and 1",
+                                entry.line
+                            ));
+                        }
+                    }
+                    if let Some(entry) = section.get("max") {
+                        scenario.corn_max = entry.parse()?;
+                    }
+                }
+                "objectives" => {
+                    for entry in &section.entries {
+                        scenario.objectives.push(match entry.key.as_str() {
+                            "deliver" => Objective::Deliver(entry.parse()?),
+                            "eat" => Objective::Eat(entry.parse()?),
+                            "no_starvation" if entry.parse::<bool>()? => Objective::NoStarvation,
+                            "no_starvation" => continue,
+                            "population" => Objective::Population(entry.parse()?),
+                            _ => {
+                                return Err(format!(
+                                    "{}: unknown objective `{}`, expected deliver, eat, \
+                                    no_starvation or population",
+                                    entry.line, entry.key
+                                ))
+                            }
+                        });
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "{}: unknown section [{}]",
+                        section.line, section.name
+                    ))
+                }
+            }
+        }
+        scenario.time_limit = time_limit.ok_or("missing the [scenario] section")?;
+        if let Some(map) = &scenario.map {
+            // Agents and corn each need a cell of their own to start on.
+            let cells = map.cells.iter().filter(|cell| cell.is_passable()).count();
+            let life = colony_life(Some(&scenario), &[])?;
+            let agents =
+                scenario.scripts.len().max(1) * life.initial_population + scenario.predators;
+            if cells < agents {
+                return Err(format!(
+                    "{map_line}: the map has {cells} cells to stand on, too few for {agents} agents"
+                ));
+            }
+            if cells < scenario.corn_max {
+                return Err(format!(
+                    "{map_line}: the map has {cells} cells to stand on, too few for {} corn",
+                    scenario.corn_max
+                ));
+            }
+        }
+        Ok(scenario)
+    }
+}
+
+/// The colony parameters of a world: the defaults with the overrides of the scenario, if any,
+/// and `params` on top of them, such as the ones a sweep tries.
+pub(crate) fn colony_life(
+    scenario: Option<&Scenario>,
+    params: &[(String, f64)],
+) -> Result<LifeCycle, String> {
+    let mut life = LifeCycle::default();
+    let overrides = scenario.map_or(&[][..], |scenario| &scenario.life);
+    for (name, value) in overrides.iter().chain(params) {
+        life.set(name, *value)?;
+    }
+    Ok(life)
+}
+
+/// Parse the map, one `row` entry per row of the board, with a character per cell:
+///
+/// * `#` wall, `~` water, `:` mud, `=` road
+/// * `.` plain ground, or `0` to `6` for ground with weeds of that height
+/// * `H` a hole, `C` a corn, `R` where a raccoon starts, `P` where a predator starts, all on
+///   plain ground
+fn parse_map(section: &ini::Section) -> Result<ScenarioMap, String> {
+    let rows: Vec<_> = section
+        .entries
+        .iter()
+        .filter(|entry| entry.key == "row")
+        .collect();
+    if rows.len() != BOARD_SIZE {
+        return Err(format!(
+            "{}: the map needs {BOARD_SIZE} rows, not {}",
+            section.line,
+            rows.len()
+        ));
+    }
+    let mut map = ScenarioMap {
+        cells: vec![MapCell::Empty(0); BOARD_SIZE * BOARD_SIZE],
+        holes: vec![],
+        corn: vec![],
+        raccoon_spawns: vec![],
+        predator_spawns: vec![],
+    };
+    for (y, row) in rows.iter().enumerate() {
+        let chars: Vec<char> = row.value.chars().collect();
+        if chars.len() != BOARD_SIZE {
+            return Err(format!(
+                "{}: a row needs {BOARD_SIZE} cells, not {}",
+                row.line,
+                chars.len()
+            ));
+        }
+        for (x, c) in chars.into_iter().enumerate() {
+            let pos = pos2(x as f32, y as f32);
+            map.cells[x + y * BOARD_SIZE] = match c {
+                '#' => MapCell::Wall,
+                '~' => MapCell::Water,
+                ':' => MapCell::Mud,
+                '=' => MapCell::Road,
+                '.' => MapCell::Empty(0),
+                '0'..='6' => MapCell::Empty(c as u8 - b'0'),
+                'H' | 'C' | 'R' | 'P' => {
+                    match c {
+                        'H' => map.holes.push(pos),
+                        'C' => map.corn.push(pos),
+                        'R' => map.raccoon_spawns.push(pos),
+                        _ => map.predator_spawns.push(pos),
+                    }
+                    MapCell::Empty(0)
+                }
+                _ => return Err(format!("{}: unknown map cell `{c}`", row.line)),
+            };
+        }
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Result<Scenario, String> {
+        Scenario::parse(src, Path::new(""))
+    }
+
+    #[test]
+    fn time_limit_has_to_be_positive() {
+        let err = parse("[scenario]\nname = Quick\ntime_limit = 0\n")
+            .err()
+            .unwrap();
+        assert_eq!(err, "3: the time limit has to be positive");
+        let scenario = parse("[scenario]\nname = Quick\ntime_limit = 5\n").unwrap();
+        assert_eq!(scenario.time_limit, 5);
+        assert!(parse("[team]\nscript = a.rscl\n").is_err());
+    }
+
+    #[test]
+    fn map_needs_room_for_everyone() {
+        let map = |row: &str| format!("[map]\n{}", format!("row = {row}\n").repeat(BOARD_SIZE));
+        let src = format!(
+            "[scenario]\nname = Walled\ntime_limit = 5\n{}",
+            map("############")
+        );
+        let err = parse(&src).err().unwrap();
+        assert_eq!(
+            err,
+            "4: the map has 0 cells to stand on, too few for 3 agents"
+        );
+
+        let src = format!(
+            "[scenario]\nname = Crowded\ntime_limit = 5\n{}[corn]\nmax = 13\n",
+            map("#.##########")
+        );
+        let err = parse(&src).err().unwrap();
+        assert_eq!(
+            err,
+            "4: the map has 12 cells to stand on, too few for 13 corn"
+        );
+
+        let err = parse("[scenario]\nname = Lucky\ntime_limit = 5\n[corn]\nchance = 1.5\n")
+            .err()
+            .unwrap();
+        assert_eq!(err, "5: the chance has to be between 0 and 1");
+    }
+
+    #[test]
+    fn goals_fail_when_the_time_is_up() {
+        let scenario = parse(
+            "[scenario]\nname = Feast\ntime_limit = 10\n[objectives]\ndeliver = 3\neat = 2\n",
+        )
+        .unwrap();
+        let mut progress = Progress::new(&scenario);
+        let tally = Tally {
+            eaten: 2,
+            ..Tally::default()
+        };
+        progress.judge(&scenario, &tally, false);
+        assert_eq!(progress.statuses, [Status::Pending, Status::Succeeded]);
+        assert_eq!(progress.outcome(), Status::Pending);
+        // A goal reached once stays reached.
+        progress.judge(&scenario, &Tally::default(), true);
+        assert_eq!(progress.statuses, [Status::Failed, Status::Succeeded]);
+        assert_eq!(progress.outcome(), Status::Failed);
+    }
+
+    #[test]
+    fn conditions_succeed_when_the_time_is_up() {
+        let scenario = parse(
+            "[scenario]\nname = Survive\ntime_limit = 10\n\
+            [objectives]\nno_starvation = true\npopulation = 2\n",
+        )
+        .unwrap();
+        let alive = Tally {
+            population: 2,
+            ..Tally::default()
+        };
+        let mut progress = Progress::new(&scenario);
+        progress.judge(&scenario, &alive, false);
+        assert_eq!(progress.outcome(), Status::Pending);
+        progress.judge(&scenario, &alive, true);
+        assert_eq!(progress.outcome(), Status::Succeeded);
+
+        let mut progress = Progress::new(&scenario);
+        let starved = Tally {
+            starved: true,
+            population: 2,
+            ..Tally::default()
+        };
+        progress.judge(&scenario, &starved, false);
+        assert_eq!(progress.statuses, [Status::Failed, Status::Pending]);
+        assert_eq!(progress.outcome(), Status::Failed);
+    }
+}
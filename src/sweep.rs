@@ -0,0 +1,248 @@
+//! Running many independent worlds in parallel over a grid of seeds and colony parameters, to see
+//! how the parameters affect the colony beyond the luck of a single world.
+
+use std::{fmt::Write, process::ExitCode};
+
+use crate::{
+    cli::Options,
+    headless::{json_string, run_parallel, separator, simulate, unique_errors, Report},
+    raccoon::set_logging_events,
+    scenario::colony_life,
+};
+
+/// The metrics compared across parameters, with how to read them from a report.
+const METRICS: [(&str, fn(&Report) -> f64); 7] = [
+    ("corn_eaten", |report| report.corn_eaten() as f64),
+    ("delivered", |report| report.delivered() as f64),
+    ("average_satiety", |report| report.average_satiety()),
+    ("starvation_ticks", |report| report.starving_ticks() as f64),
+    ("population", |report| report.population as f64),
+    ("deaths", |report| report.deaths as f64),
+    ("script_errors", |report| report.script_errors() as f64),
+];
+
+/// The results of all the seeds with one combination of parameters.
+struct Row {
+    params: Vec<(String, f64)>,
+    runs: usize,
+    /// Mean and variance of each of `METRICS`.
+    stats: Vec<(f64, f64)>,
+}
+
+pub(crate) fn run(options: &Options) -> ExitCode {
+    // The events of many worlds interleave into noise.
+    set_logging_events(false);
+
+    let scenario = match options.load_scenario() {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let combinations = combinations(&options.params);
+    // The swept values go on top of the scenario's, so check them the same way.
+    for combination in &combinations {
+        if let Err(e) = colony_life(scenario.as_ref(), combination) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let jobs: Vec<(usize, u64)> = (0..combinations.len())
+        .flat_map(|i| options.seeds.iter().map(move |seed| (i, *seed)))
+        .collect();
+    println!(
+        "Running {} worlds of {} ticks",
+        jobs.len(),
+        options.ticks(scenario.as_ref())
+    );
+    let reports = run_parallel(&jobs, options.threads, |&(combination, seed)| {
+        let mut options = options.clone();
+        options.seed = Some(seed);
+        simulate(&options, &combinations[combination])
+    });
+
+    let errors = unique_errors(&reports);
+    for e in &errors {
+        eprintln!("Error: {e}");
+    }
+
+    let rows: Vec<Row> = combinations
+        .into_iter()
+        .enumerate()
+        .map(|(i, params)| {
+            let runs: Vec<&Report> = jobs
+                .iter()
+                .zip(&reports)
+                .filter(|((combination, _), _)| *combination == i)
+                .map(|(_, report)| report)
+                .collect();
+            let stats = METRICS
+                .iter()
+                .map(|(_, get)| mean_variance(runs.iter().map(|report| get(report))))
+                .collect();
+            Row {
+                params,
+                runs: runs.len(),
+                stats,
+            }
+        })
+        .collect();
+
+    print_table(&rows);
+    if let Some(path) = &options.report {
+        if let Err(e) = std::fs::write(path, to_json(&rows)) {
+            eprintln!("Failed to write the report to {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Every combination of the values of the parameters. Without parameters, there is a single
+/// combination with the defaults.
+fn combinations(params: &[(String, Vec<f64>)]) -> Vec<Vec<(String, f64)>> {
+    let mut combinations = vec![vec![]];
+    for (name, values) in params {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination: Vec<(String, f64)>| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((name.clone(), *value));
+                    combination
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+/// The mean and the sample variance of `values`.
+fn mean_variance(values: impl Iterator<Item = f64> + Clone) -> (f64, f64) {
+    let n = values.clone().count();
+    if n == 0 {
+        return (0., 0.);
+    }
+    let mean = values.clone().sum::<f64>() / n as f64;
+    if n == 1 {
+        return (mean, 0.);
+    }
+    let variance = values.map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+    (mean, variance)
+}
+
+fn print_table(rows: &[Row]) {
+    const WIDTH: usize = 18;
+    let Some(first) = rows.first() else {
+        return;
+    };
+    let mut line = String::new();
+    for (name, _) in &first.params {
+        let _ = write!(line, "{name:>WIDTH$}");
+    }
+    let _ = write!(line, "{:>6}", "runs");
+    for (name, _) in METRICS {
+        let _ = write!(line, "{name:>WIDTH$}");
+    }
+    println!("{line}");
+    println!("{:>w$}", "mean (variance)", w = line.len());
+
+    for row in rows {
+        let mut line = String::new();
+        for (_, value) in &row.params {
+            let _ = write!(line, "{value:>WIDTH$}");
+        }
+        let _ = write!(line, "{:>6}", row.runs);
+        for (mean, variance) in &row.stats {
+            let _ = write!(line, "{:>WIDTH$}", format!("{mean:.3} ({variance:.3})"));
+        }
+        println!("{line}");
+    }
+}
+
+fn to_json(rows: &[Row]) -> String {
+    let mut out = String::new();
+    out.push_str("[\n");
+    for (i, row) in rows.iter().enumerate() {
+        let params: Vec<String> = row
+            .params
+            .iter()
+            .map(|(name, value)| format!("{}: {value}", json_string(name)))
+            .collect();
+        let stats: Vec<String> = METRICS
+            .iter()
+            .zip(&row.stats)
+            .map(|((name, _), (mean, variance))| {
+                format!(
+                    "{}: {{\"mean\": {mean}, \"variance\": {variance}}}",
+                    json_string(name)
+                )
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "  {{\"params\": {{{}}}, \"runs\": {}, \"metrics\": {{{}}}}}{}",
+            params.join(", "),
+            row.runs,
+            stats.join(", "),
+            separator(i, rows.len())
+        );
+    }
+    out.push_str("]\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::scenario::Scenario;
+
+    #[test]
+    fn combinations_of_all_values() {
+        let params = vec![
+            ("a".to_string(), vec![1., 2.]),
+            ("b".to_string(), vec![3., 4., 5.]),
+        ];
+        let all = combinations(&params);
+        assert_eq!(all.len(), 6);
+        assert_eq!(all[0], [("a".to_string(), 1.), ("b".to_string(), 3.)]);
+        assert_eq!(all[5], [("a".to_string(), 2.), ("b".to_string(), 5.)]);
+        assert_eq!(combinations(&[]), vec![Vec::<(String, f64)>::new()]);
+        assert!(combinations(&[("a".to_string(), vec![])]).is_empty());
+    }
+
+    #[test]
+    fn swept_params_override_the_scenario() {
+        let src = "[scenario]\nname = Lean\ntime_limit = 10\n\
+            [life]\nhunger_rate = 0.004\nmaturity = 50\n";
+        let scenario = Scenario::parse(src, Path::new("")).unwrap();
+        let params = vec![("hunger_rate".to_string(), vec![0.01, 0.02])];
+        let lives: Vec<_> = combinations(&params)
+            .iter()
+            .map(|combination| colony_life(Some(&scenario), combination).unwrap())
+            .collect();
+        assert_eq!(lives[0].hunger_rate, 0.01);
+        assert_eq!(lives[1].hunger_rate, 0.02);
+        // What the sweep leaves alone is still the scenario's.
+        assert_eq!(lives[0].maturity, 50);
+
+        let params = vec![("hunger".to_string(), vec![0.01])];
+        assert!(colony_life(Some(&scenario), &combinations(&params)[0]).is_err());
+    }
+
+    #[test]
+    fn mean_and_sample_variance() {
+        assert_eq!(mean_variance([].into_iter()), (0., 0.));
+        assert_eq!(mean_variance([3.].into_iter()), (3., 0.));
+        assert_eq!(mean_variance([2., 4., 6.].into_iter()), (4., 4.));
+    }
+}
@@ -0,0 +1,268 @@
+//! Comparing raccoon scripts over the same set of worlds, to settle which one does better.
+//!
+//! Each script either gets worlds of its own, or all the scripts share each world as rival teams
+//! with `--versus`. Since the seed decides the map, every script sees the same maps either way.
+
+use std::{fmt::Write, path::PathBuf, process::ExitCode};
+
+use crate::{
+    cli::Options,
+    headless::{json_string, run_parallel, separator, simulate_with_replay, unique_errors, Report},
+    raccoon::set_logging_events,
+};
+
+/// A world with the scripts that play in it, as indices into the tournament's scripts.
+struct Match {
+    seed: u64,
+    entrants: Vec<usize>,
+    replay: Option<PathBuf>,
+}
+
+/// How a script did in a single match.
+#[derive(Clone, Copy, Default)]
+struct Score {
+    /// Corns its raccoons took from the ground.
+    collected: usize,
+    /// Ticks until the last of its raccoons died, or the whole match if some survived.
+    survival: usize,
+    delivered: usize,
+}
+
+impl Score {
+    fn of_team(report: &Report, team: usize) -> Self {
+        let mut score = Self::default();
+        let mut survived = false;
+        for raccoon in report
+            .raccoons
+            .iter()
+            .filter(|raccoon| raccoon.team == team)
+        {
+            score.collected += raccoon.collected;
+            score.delivered += raccoon.delivered;
+            match raccoon.died() {
+                Some(tick) => score.survival = score.survival.max(tick),
+                None => survived = true,
+            }
+        }
+        if survived {
+            score.survival = report.ticks;
+        }
+        score
+    }
+}
+
+/// The total of a script's scores over all its matches.
+struct Standing {
+    script: usize,
+    matches: usize,
+    collected: usize,
+    survival: usize,
+    delivered: usize,
+}
+
+impl Standing {
+    fn mean(&self, total: usize) -> f64 {
+        if self.matches == 0 {
+            0.
+        } else {
+            total as f64 / self.matches as f64
+        }
+    }
+}
+
+pub(crate) fn run(options: &Options) -> ExitCode {
+    if options.scripts.len() < 2 {
+        eprintln!("A tournament needs at least two scripts");
+        return ExitCode::FAILURE;
+    }
+    // The events of many worlds interleave into noise.
+    set_logging_events(false);
+
+    if let Some(dir) = &options.replays {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!(
+                "Failed to create the replay directory {}: {e}",
+                dir.display()
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let scripts = &options.scripts;
+    let entrants: Vec<Vec<usize>> = if options.versus {
+        vec![(0..scripts.len()).collect()]
+    } else {
+        (0..scripts.len()).map(|script| vec![script]).collect()
+    };
+    let matches: Vec<Match> = options
+        .seeds
+        .iter()
+        .flat_map(|seed| {
+            entrants
+                .iter()
+                .map(move |entrants| (*seed, entrants.clone()))
+        })
+        .enumerate()
+        .map(|(i, (seed, entrants))| Match {
+            seed,
+            entrants,
+            replay: options
+                .replays
+                .as_ref()
+                .map(|dir| dir.join(format!("match_{i:03}.jsonl"))),
+        })
+        .collect();
+
+    let scenario = match options.load_scenario() {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!(
+        "Playing {} matches of {} ticks between {} scripts",
+        matches.len(),
+        options.ticks(scenario.as_ref()),
+        scripts.len()
+    );
+    let reports = run_parallel(&matches, options.threads, |game| {
+        let mut options = options.clone();
+        options.seed = Some(game.seed);
+        options.scripts = game.entrants.iter().map(|i| scripts[*i].clone()).collect();
+        simulate_with_replay(&options, &[], game.replay.as_deref())
+    });
+
+    let errors = unique_errors(&reports);
+    for e in &errors {
+        eprintln!("Error: {e}");
+    }
+
+    // The scores of each match, by the entrant's team in the match.
+    let scores: Vec<Vec<Score>> = matches
+        .iter()
+        .zip(&reports)
+        .map(|(game, report)| {
+            (0..game.entrants.len())
+                .map(|team| Score::of_team(report, team))
+                .collect()
+        })
+        .collect();
+
+    let mut standings: Vec<Standing> = (0..scripts.len())
+        .map(|script| Standing {
+            script,
+            matches: 0,
+            collected: 0,
+            survival: 0,
+            delivered: 0,
+        })
+        .collect();
+    for (game, scores) in matches.iter().zip(&scores) {
+        for (script, score) in game.entrants.iter().zip(scores) {
+            let standing = &mut standings[*script];
+            standing.matches += 1;
+            standing.collected += score.collected;
+            standing.survival += score.survival;
+            standing.delivered += score.delivered;
+        }
+    }
+    // Rank by corn first, and break ties by survival and then by deliveries.
+    standings.sort_by(|a, b| {
+        let key = |s: &Standing| [s.collected, s.survival, s.delivered];
+        key(b).cmp(&key(a))
+    });
+
+    print_standings(&standings, scripts);
+    if let Some(path) = &options.report {
+        let json = to_json(options.versus, &standings, scripts, &matches, &scores);
+        if let Err(e) = std::fs::write(path, json) {
+            eprintln!("Failed to write the report to {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+    }
+
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn print_standings(standings: &[Standing], scripts: &[String]) {
+    let width = scripts.iter().map(|script| script.len()).max().unwrap_or(0);
+    println!(
+        "{:>4}  {:<width$}  {:>7}  {:>9}  {:>9}  {:>9}",
+        "rank", "script", "matches", "collected", "survival", "delivered"
+    );
+    println!(
+        "{:>4}  {:<width$}  {:>7}  {:>31}",
+        "", "", "", "mean per match"
+    );
+    for (rank, standing) in standings.iter().enumerate() {
+        println!(
+            "{:>4}  {:<width$}  {:>7}  {:>9.2}  {:>9.1}  {:>9.2}",
+            rank + 1,
+            scripts[standing.script],
+            standing.matches,
+            standing.mean(standing.collected),
+            standing.mean(standing.survival),
+            standing.mean(standing.delivered)
+        );
+    }
+}
+
+fn to_json(
+    versus: bool,
+    standings: &[Standing],
+    scripts: &[String],
+    matches: &[Match],
+    scores: &[Vec<Score>],
+) -> String {
+    let mut out = String::new();
+    // Writing to a String cannot fail.
+    let _ = writeln!(out, "{{\n  \"versus\": {versus},\n  \"standings\": [");
+    for (i, standing) in standings.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "    {{\"rank\": {}, \"script\": {}, \"matches\": {}, \"collected\": {}, \
+            \"survival\": {}, \"delivered\": {}}}{}",
+            i + 1,
+            json_string(&scripts[standing.script]),
+            standing.matches,
+            standing.mean(standing.collected),
+            standing.mean(standing.survival),
+            standing.mean(standing.delivered),
+            separator(i, standings.len())
+        );
+    }
+    let _ = writeln!(out, "  ],\n  \"matches\": [");
+    for (i, (game, scores)) in matches.iter().zip(scores).enumerate() {
+        let results: Vec<String> = game
+            .entrants
+            .iter()
+            .zip(scores)
+            .map(|(script, score)| {
+                format!(
+                    "{{\"script\": {}, \"collected\": {}, \"survival\": {}, \"delivered\": {}}}",
+                    json_string(&scripts[*script]),
+                    score.collected,
+                    score.survival,
+                    score.delivered
+                )
+            })
+            .collect();
+        let replay = game.replay.as_ref().map_or("null".to_string(), |path| {
+            json_string(&path.display().to_string())
+        });
+        let _ = writeln!(
+            out,
+            "    {{\"seed\": {}, \"replay\": {replay}, \"results\": [{}]}}{}",
+            game.seed,
+            results.join(", "),
+            separator(i, matches.len())
+        );
+    }
+    let _ = writeln!(out, "  ]\n}}");
+    out
+}
@@ -0,0 +1,752 @@
+//! The simulated world and everything in it, which runs the same with or without a window.
+
+use std::{
+    cell::{Cell, RefCell},
+    path::Path,
+    rc::Rc,
+};
+
+use eframe::epaint::{pos2, Color32, Pos2};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use ruscal::bytecode::ByteCode;
+
+use crate::{
+    assets::Assets,
+    cli::Options,
+    director::Director,
+    pathfinding::{Algorithm, Movement},
+    raccoon::{
+        compile_program, logging_events, reserve_all, resolve_moves, Action, AgentKind, LifeCycle,
+        Raccoon, RaccoonState, Reservations, RestartPolicy, TrafficRules, DEFAULT_STEP_BUDGET,
+    },
+    scenario::{colony_life, Scenario},
+};
+
+pub(crate) const BOARD_SIZE: usize = 12;
+pub(crate) const BOARD_SIZE_I: i32 = BOARD_SIZE as i32;
+/// Size of a cell in pixels, which paths are also laid out in.
+pub(crate) const CELL_SIZE: usize = 64;
+pub(crate) const CELL_SIZE_F: f32 = CELL_SIZE as f32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MapCell {
+    Wall,
+    /// Plain ground with the given weed level. Tall weeds slow raccoons down.
+    Empty(u8),
+    Water,
+    Mud,
+    /// Cleared ground that is always fast to walk on.
+    Road,
+}
+
+impl MapCell {
+    /// Names of the terrains as they appear in data files.
+    pub(crate) const NAMES: [&'static str; 5] = ["wall", "empty", "water", "mud", "road"];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Wall => "wall",
+            Self::Empty(_) => "empty",
+            Self::Water => "water",
+            Self::Mud => "mud",
+            Self::Road => "road",
+        }
+    }
+
+    /// Number of ticks it takes to step into this cell, or `None` if it is impassable.
+    pub(crate) fn move_cost(&self) -> Option<u32> {
+        match self {
+            Self::Wall | Self::Water => None,
+            Self::Empty(weed) => Some(1 + (*weed as u32 + 1) / 3),
+            Self::Mud => Some(3),
+            Self::Road => Some(1),
+        }
+    }
+
+    pub(crate) fn is_passable(&self) -> bool {
+        self.move_cost().is_some()
+    }
+}
+
+pub(crate) const HOLE_CAPACITY: usize = 5;
+
+/// Number of holes each team starts with.
+const HOLES_PER_TEAM: usize = 2;
+
+/// Most points kept in `World::population_history`. Beyond that, every other point is dropped
+/// and points are taken half as often, so that the chart covers the whole run in bounded memory.
+const MAX_HISTORY: usize = 1000;
+
+/// Colours of the teams when there is more than one, in the order of their scripts.
+pub(crate) const TEAM_COLORS: [Color32; 6] = [
+    Color32::from_rgb(255, 96, 96),
+    Color32::from_rgb(96, 160, 255),
+    Color32::from_rgb(255, 224, 64),
+    Color32::from_rgb(96, 224, 96),
+    Color32::from_rgb(224, 128, 255),
+    Color32::from_rgb(255, 160, 64),
+];
+
+/// The raccoons running the same script, and the holes they own.
+pub(crate) struct Team {
+    /// The file name of the script.
+    pub name: String,
+    pub color: Color32,
+    /// The compiled script, or `None` if it failed to compile.
+    pub program: Option<Rc<ByteCode>>,
+}
+
+/// How well a team is doing.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TeamStats {
+    pub alive: usize,
+    pub delivered: usize,
+    pub eaten: usize,
+    /// Ticks until the last raccoon of the team died, or all of them so far if some are alive.
+    pub survival: usize,
+}
+
+pub(crate) struct Hole {
+    pub pos: Pos2,
+    /// Only raccoons of this team can use the hole.
+    pub team: usize,
+    pub occupied: Cell<bool>,
+    /// Number of corns stored in the hole.
+    pub stored: Cell<usize>,
+    pub capacity: usize,
+}
+
+impl Hole {
+    pub(crate) fn new(pos: Pos2, team: usize) -> Self {
+        Self {
+            pos,
+            team,
+            occupied: Cell::new(false),
+            stored: Cell::new(0),
+            capacity: HOLE_CAPACITY,
+        }
+    }
+}
+
+/// What agents know about each other, refreshed at the start of every tick.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AgentInfo {
+    pub id: usize,
+    pub kind: AgentKind,
+    pub team: usize,
+    pub pos: Pos2,
+    pub in_hole: bool,
+}
+
+/// A value that a raccoon broadcast to its team, delivered at the end of the tick.
+pub(crate) struct Message {
+    pub from: usize,
+    pub team: usize,
+    pub value: i64,
+}
+
+/// Handles to the parts of the world that agents and their scripts can see.
+#[derive(Clone)]
+pub(crate) struct SharedWorld {
+    pub map: Rc<RefCell<Vec<MapCell>>>,
+    pub items: Rc<RefCell<Vec<Pos2>>>,
+    pub holes: Rc<RefCell<Vec<Hole>>>,
+    pub agents: Rc<RefCell<Vec<AgentInfo>>>,
+    pub path_algorithm: Rc<Cell<Algorithm>>,
+    pub movement: Rc<Cell<Movement>>,
+    pub traffic: Rc<Cell<TrafficRules>>,
+    /// Cells the agents are about to walk through, for cooperative path planning.
+    pub reservations: Rc<RefCell<Reservations>>,
+    /// Messages broadcast in this tick.
+    pub messages: Rc<RefCell<Vec<Message>>>,
+    /// The source of all randomness in the world, so that a seed reproduces a run.
+    pub rng: Rc<RefCell<StdRng>>,
+    /// The id of the raccoon controlled with the keyboard, if any.
+    pub player: Rc<Cell<Option<usize>>>,
+    /// The action the player chose for the player's raccoon since the last tick.
+    pub player_action: Rc<Cell<Option<Action>>>,
+    /// Number of ticks simulated so far, which the agents stamp their script errors with.
+    pub ticks: Rc<Cell<usize>>,
+}
+
+/// An agent that has died, kept for the statistics.
+pub(crate) struct Departed {
+    pub id: usize,
+    pub kind: AgentKind,
+    pub state: Rc<RefCell<RaccoonState>>,
+}
+
+/// The whole simulation: the map, the agents and the colony's history.
+pub(crate) struct World {
+    pub shared: SharedWorld,
+    /// All agents, raccoons and predators alike.
+    pub raccoons: Vec<Raccoon>,
+    /// Agents that died, in the order they died.
+    pub departed: Vec<Departed>,
+    pub teams: Vec<Team>,
+    pub life: LifeCycle,
+    /// The world script that stages events, if any.
+    pub director: Option<Director>,
+    /// Chance of a corn appearing in a tick.
+    pub corn_chance: f64,
+    /// No corn appears while there are this many on the ground.
+    pub corn_max: usize,
+    /// The restart policy that raccoons start with. Newborns take their parent's.
+    restart_policy: RestartPolicy,
+    /// Steps per tick that the scripts of the agents and the director can take.
+    step_budget: usize,
+    next_id: usize,
+    debug_output: bool,
+    pub births: usize,
+    pub deaths: usize,
+    /// Number of living raccoons over time, as ticks and populations, taken every
+    /// `history_interval` ticks.
+    pub population_history: Vec<(usize, usize)>,
+    history_interval: usize,
+}
+
+impl World {
+    /// Generate a world and spawn the agents. Problems that leave the world incomplete, such as a
+    /// script that does not compile, are added to `errors`.
+    pub(crate) fn new(
+        options: &Options,
+        params: &[(String, f64)],
+        assets: &Assets,
+        errors: &mut Vec<String>,
+    ) -> Self {
+        Self::with_scenario(options, params, None, assets, errors)
+    }
+
+    /// Like `new`, but the scenario, if any, decides the map, the scripts, where the agents start
+    /// and how corn grows. Scripts and a seed given in `options` take precedence over its own, and
+    /// so do the colony parameters in `params` over those of its `[life]`.
+    pub(crate) fn with_scenario(
+        options: &Options,
+        params: &[(String, f64)],
+        scenario: Option<&Scenario>,
+        assets: &Assets,
+        errors: &mut Vec<String>,
+    ) -> Self {
+        let mut rng = match options.seed.or(scenario.and_then(|scenario| scenario.seed)) {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let life = colony_life(scenario, params).unwrap_or_else(|e| {
+            errors.push(e);
+            LifeCycle::default()
+        });
+        let script_paths = match scenario {
+            Some(scenario) if options.scripts.is_empty() => &scenario.scripts,
+            _ => &options.scripts,
+        };
+        let scenario_map = scenario.and_then(|scenario| scenario.map.as_ref());
+        let restart_policy = options
+            .restart_policy
+            .or(scenario.and_then(|scenario| scenario.restart_policy))
+            .unwrap_or_default();
+        let step_budget = options
+            .step_budget
+            .or(scenario.and_then(|scenario| scenario.step_budget))
+            .unwrap_or(DEFAULT_STEP_BUDGET);
+
+        // Each script makes a team of its own.
+        let team_count = script_paths.len().max(1);
+        let (map, holes, items) = match scenario_map {
+            Some(scenario_map) => {
+                let holes: Vec<Hole> = scenario_map
+                    .holes
+                    .iter()
+                    .enumerate()
+                    .map(|(i, pos)| Hole::new(*pos, i % team_count))
+                    .collect();
+                (scenario_map.cells.clone(), holes, scenario_map.corn.clone())
+            }
+            None => {
+                let map = generate_map(&mut rng);
+                let holes: Vec<Hole> = (0..team_count * HOLES_PER_TEAM)
+                    .filter_map(|i| {
+                        let pos = generate_pos(&mut rng, |pos| is_blocked(pos, &map, &[]))?;
+                        Some(Hole::new(pos, i / HOLES_PER_TEAM))
+                    })
+                    .collect();
+                (map, holes, vec![])
+            }
+        };
+        let holes = Rc::new(RefCell::new(holes));
+        let map = Rc::new(RefCell::new(map));
+
+        let shared = SharedWorld {
+            map,
+            items: Rc::new(RefCell::new(items)),
+            holes,
+            agents: Rc::new(RefCell::new(vec![])),
+            path_algorithm: Rc::new(Cell::new(Algorithm::Auto)),
+            movement: Rc::new(Cell::new(Movement::FourWay)),
+            traffic: Rc::new(Cell::new(TrafficRules::default())),
+            reservations: Rc::new(RefCell::new(Reservations::new())),
+            messages: Rc::new(RefCell::new(vec![])),
+            rng: Rc::new(RefCell::new(rng)),
+            player: Rc::new(Cell::new(None)),
+            player_action: Rc::new(Cell::new(None)),
+            ticks: Rc::new(Cell::new(0)),
+        };
+
+        // Scripts given on the command line are plain files, while the default one is an asset.
+        let scripts: Vec<(String, Result<String, String>)> = if script_paths.is_empty() {
+            let path = assets.path("raccoon_script");
+            let name = path.clone().unwrap_or("raccoon_script").to_string();
+            vec![(name, path.and_then(|path| assets.read_to_string(path)))]
+        } else {
+            script_paths
+                .iter()
+                .map(|path| {
+                    let source = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"));
+                    (path.clone(), source)
+                })
+                .collect()
+        };
+
+        let mut raccoon_spawns = scenario_map
+            .map(|scenario_map| scenario_map.raccoon_spawns.iter())
+            .into_iter()
+            .flatten();
+        let mut raccoons = vec![];
+        let mut teams = vec![];
+        for (team, (path, source)) in scripts.into_iter().enumerate() {
+            // A lone team keeps the raccoons' own colours.
+            let color = if team_count == 1 {
+                None
+            } else {
+                Some(TEAM_COLORS[team % TEAM_COLORS.len()])
+            };
+            let program = source.and_then(|source| {
+                compile_program(&path, &source, options.disasm)
+                    .map(Rc::new)
+                    .map_err(|e| format!("Compile error: {e}"))
+            });
+            teams.push(Team {
+                name: path,
+                color: color.unwrap_or(Color32::WHITE),
+                program: program.as_ref().ok().cloned(),
+            });
+            let program = match program {
+                Ok(program) => program,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
+            for _ in 0..life.initial_population {
+                let raccoon = Raccoon::new(
+                    raccoons.len(),
+                    AgentKind::Raccoon,
+                    &shared,
+                    &program,
+                    step_budget,
+                    options.debug_output,
+                );
+                {
+                    let mut state = raccoon.state.borrow_mut();
+                    state.team = team;
+                    state.restart_policy = restart_policy;
+                    // The rest start at random on a scenario's map, which can have walls
+                    // anywhere, so they are kept off walls and other raccoons.
+                    let pos = match raccoon_spawns.next() {
+                        Some(pos) => Some(*pos),
+                        None if scenario_map.is_some() => {
+                            let taken: Vec<Pos2> = raccoons
+                                .iter()
+                                .map(|raccoon: &Raccoon| raccoon.state.borrow().pos)
+                                .collect();
+                            let pos = generate_pos(&mut shared.rng.borrow_mut(), |pos| {
+                                is_blocked(pos, &shared.map.borrow(), &taken)
+                            });
+                            // The rest of the team does not fit on the map.
+                            if pos.is_none() {
+                                break;
+                            }
+                            pos
+                        }
+                        None => None,
+                    };
+                    if let Some(pos) = pos {
+                        state.pos = pos;
+                        state.prev_pos = pos;
+                    }
+                    if let Some(color) = color {
+                        state.tint = color;
+                    }
+                }
+                raccoons.push(raccoon);
+            }
+        }
+
+        let predator_program = assets.path("predator_script").and_then(|path| {
+            let source = assets.read_to_string(path)?;
+            compile_program(path, &source, options.disasm)
+                .map_err(|e| format!("Predator compile error: {e}"))
+        });
+        let predators = scenario.map_or(1, |scenario| scenario.predators);
+        let mut predator_spawns = scenario_map
+            .map(|scenario_map| scenario_map.predator_spawns.iter())
+            .into_iter()
+            .flatten();
+        match predator_program {
+            Ok(bytecode) => {
+                let bytecode = Rc::new(bytecode);
+                for _ in 0..predators {
+                    let predator = Raccoon::new(
+                        raccoons.len(),
+                        AgentKind::Predator,
+                        &shared,
+                        &bytecode,
+                        step_budget,
+                        options.debug_output,
+                    );
+                    {
+                        let mut state = predator.state.borrow_mut();
+                        let pos = match predator_spawns.next() {
+                            Some(pos) => Some(*pos),
+                            None => generate_pos(&mut shared.rng.borrow_mut(), |pos| {
+                                is_blocked(pos, &shared.map.borrow(), &[])
+                            }),
+                        };
+                        // Nor do predators on a map without a cell to stand on.
+                        let Some(pos) = pos else {
+                            break;
+                        };
+                        state.pos = pos;
+                        state.prev_pos = state.pos;
+                        state.tint = Color32::WHITE;
+                    }
+                    raccoons.push(predator);
+                }
+            }
+            // Without predators, a broken predator script does not matter.
+            Err(_) if predators == 0 => {}
+            Err(e) => errors.push(e),
+        }
+
+        let director_path = options
+            .director
+            .as_ref()
+            .or(scenario.and_then(|scenario| scenario.director.as_ref()));
+        let director = director_path.and_then(|path| {
+            let director = std::fs::read_to_string(path)
+                .map_err(|e| format!("{path}: {e}"))
+                .and_then(|source| {
+                    Director::new(
+                        path,
+                        &source,
+                        &shared,
+                        step_budget,
+                        options.disasm,
+                        options.debug_output,
+                    )
+                });
+            director.map_err(|e| errors.push(e)).ok()
+        });
+
+        Self {
+            shared,
+            next_id: raccoons.len(),
+            raccoons,
+            departed: vec![],
+            teams,
+            life,
+            director,
+            corn_chance: scenario.map_or(0.1, |scenario| scenario.corn_chance),
+            corn_max: scenario.map_or(10, |scenario| scenario.corn_max),
+            restart_policy,
+            step_budget,
+            debug_output: options.debug_output,
+            births: 0,
+            deaths: 0,
+            population_history: vec![],
+            history_interval: 1,
+        }
+    }
+
+    /// Number of ticks simulated so far.
+    pub(crate) fn ticks(&self) -> usize {
+        self.shared.ticks.get()
+    }
+
+    /// Number of living raccoons, not counting predators.
+    pub(crate) fn population(&self) -> usize {
+        self.raccoons
+            .iter()
+            .filter(|raccoon| raccoon.kind == AgentKind::Raccoon)
+            .count()
+    }
+
+    /// How each team is doing, in the order of `teams`.
+    pub(crate) fn team_stats(&self) -> Vec<TeamStats> {
+        let mut stats = vec![TeamStats::default(); self.teams.len()];
+        let living = self
+            .raccoons
+            .iter()
+            .map(|raccoon| (raccoon.kind, &raccoon.state));
+        let dead = self
+            .departed
+            .iter()
+            .map(|departed| (departed.kind, &departed.state));
+        for (kind, state) in living.chain(dead) {
+            let state = state.borrow();
+            let Some(team) = stats.get_mut(state.team) else {
+                continue;
+            };
+            if kind != AgentKind::Raccoon {
+                continue;
+            }
+            team.delivered += state.delivered;
+            team.eaten += state.ate;
+            if state.dead {
+                team.survival = team.survival.max(state.born + state.age);
+            } else {
+                team.alive += 1;
+            }
+        }
+        for team in &mut stats {
+            if 0 < team.alive {
+                team.survival = self.ticks();
+            }
+        }
+        stats
+    }
+
+    /// Advance the world by one tick.
+    pub(crate) fn tick(&mut self) {
+        self.direct();
+        let world = &self.shared;
+        *world.agents.borrow_mut() = self.raccoons.iter().map(|raccoon| raccoon.info()).collect();
+        reserve_all(&mut world.reservations.borrow_mut(), &self.raccoons);
+        for raccoon in &self.raccoons {
+            raccoon.animate(world, &self.life);
+        }
+        resolve_moves(&self.raccoons, &world.map.borrow(), world.traffic.get());
+        for message in world.messages.take() {
+            for raccoon in &self.raccoons {
+                let team = raccoon.state.borrow().team;
+                if raccoon.id != message.from
+                    && raccoon.kind == AgentKind::Raccoon
+                    && team == message.team
+                {
+                    raccoon.receive(message.value);
+                }
+            }
+        }
+
+        let (dead, alive) = std::mem::take(&mut self.raccoons)
+            .into_iter()
+            .partition(|raccoon| raccoon.state.borrow().dead);
+        self.raccoons = alive;
+        for raccoon in dead {
+            if world.player.get() == Some(raccoon.id) {
+                world.player.set(None);
+            }
+            self.deaths += 1;
+            self.departed.push(Departed {
+                id: raccoon.id,
+                kind: raccoon.kind,
+                state: raccoon.state.clone(),
+            });
+        }
+
+        let mut newborns: Vec<Raccoon> = vec![];
+        for raccoon in &self.raccoons {
+            if self.life.max_population <= self.population() + newborns.len() {
+                break;
+            }
+            let occupied = |pos| {
+                self.raccoons
+                    .iter()
+                    .chain(newborns.iter())
+                    .any(|other| other.state.borrow().pos == pos)
+            };
+            if let Some(child) = raccoon.reproduce(self.next_id, occupied, world, &self.life) {
+                self.next_id += 1;
+                newborns.push(child);
+            }
+        }
+        for child in &newborns {
+            child.state.borrow_mut().born = self.ticks();
+        }
+        self.births += newborns.len();
+        self.raccoons.extend(newborns);
+
+        let ticks = self.ticks() + 1;
+        self.shared.ticks.set(ticks);
+        if ticks % self.history_interval == 0 {
+            self.population_history.push((ticks, self.population()));
+            if MAX_HISTORY < self.population_history.len() {
+                self.history_interval *= 2;
+                let interval = self.history_interval;
+                self.population_history
+                    .retain(|(ticks, _)| ticks % interval == 0);
+            }
+        }
+
+        let mut rng = world.rng.borrow_mut();
+        if world.items.borrow().len() < self.corn_max && rng.gen::<f64>() < self.corn_chance {
+            // Corn only grows where there is room for it.
+            let pos = generate_pos(&mut rng, |pos| {
+                is_blocked(pos, &world.map.borrow(), &world.items.borrow())
+            });
+            if let Some(pos) = pos {
+                world.items.borrow_mut().push(pos);
+            }
+        }
+    }
+
+    /// Let the director stage its events, before the agents act in this tick.
+    fn direct(&mut self) {
+        let ticks = self.ticks();
+        let Some(director) = &mut self.director else {
+            return;
+        };
+        // The director avoids walling in agents where they are now.
+        *self.shared.agents.borrow_mut() =
+            self.raccoons.iter().map(|raccoon| raccoon.info()).collect();
+        let spawns = director.run(ticks);
+        let name = director.name.clone();
+        // Scripts are relative to the director's, like those of a scenario.
+        let dir = Path::new(&name).parent().unwrap_or(Path::new(""));
+        for script in spawns {
+            let path = dir.join(script).to_string_lossy().into_owned();
+            match self.spawn_raccoon(&path) {
+                Err(e) if logging_events() => {
+                    eprintln!("Director {name} failed to spawn a raccoon: {e}")
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Spawn a raccoon running the script at `path` at a free cell. It joins the team that runs
+    /// the same script, or makes a new team.
+    fn spawn_raccoon(&mut self, path: &str) -> Result<(), String> {
+        let team = match self
+            .teams
+            .iter()
+            .position(|team| same_file(&team.name, path))
+        {
+            Some(team) => team,
+            None => {
+                let source = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+                let program = compile_program(path, &source, false)
+                    .map_err(|e| format!("Compile error: {e}"))?;
+                self.teams.push(Team {
+                    name: path.to_string(),
+                    color: TEAM_COLORS[self.teams.len() % TEAM_COLORS.len()],
+                    program: Some(Rc::new(program)),
+                });
+                // A new team gets a hole of its own, like the teams the world started with.
+                let team = self.teams.len() - 1;
+                let taken: Vec<Pos2> = self
+                    .shared
+                    .holes
+                    .borrow()
+                    .iter()
+                    .map(|hole| hole.pos)
+                    .collect();
+                let pos = generate_pos(&mut self.shared.rng.borrow_mut(), |pos| {
+                    is_blocked(pos, &self.shared.map.borrow(), &taken)
+                });
+                // Without room for a hole, the team has to dig one.
+                if let Some(pos) = pos {
+                    self.shared.holes.borrow_mut().push(Hole::new(pos, team));
+                }
+                team
+            }
+        };
+        let Some(program) = &self.teams[team].program else {
+            return Err(format!("{path} did not compile"));
+        };
+
+        let taken: Vec<Pos2> = self
+            .raccoons
+            .iter()
+            .map(|raccoon| raccoon.state.borrow().pos)
+            .collect();
+        let pos = generate_pos(&mut self.shared.rng.borrow_mut(), |pos| {
+            is_blocked(pos, &self.shared.map.borrow(), &taken)
+        })
+        .ok_or_else(|| format!("no free cell for a raccoon running {path}"))?;
+
+        let raccoon = Raccoon::new(
+            self.next_id,
+            AgentKind::Raccoon,
+            &self.shared,
+            program,
+            self.step_budget,
+            self.debug_output,
+        );
+        self.next_id += 1;
+        {
+            let mut state = raccoon.state.borrow_mut();
+            state.pos = pos;
+            state.prev_pos = pos;
+            state.team = team;
+            state.restart_policy = self.restart_policy;
+            state.born = self.ticks();
+            if 1 < self.teams.len() {
+                state.tint = self.teams[team].color;
+            }
+        }
+        log_event!("The director spawned raccoon {} running {path}", raccoon.id);
+        self.raccoons.push(raccoon);
+        Ok(())
+    }
+}
+
+/// Whether two paths name the same file, even if they are spelled differently.
+fn same_file(a: &str, b: &str) -> bool {
+    let canonical = |path: &str| std::fs::canonicalize(path).unwrap_or_else(|_| path.into());
+    canonical(a) == canonical(b)
+}
+
+/// A random map with a crossroad cutting through it.
+fn generate_map(rng: &mut StdRng) -> Vec<MapCell> {
+    let mut map = vec![MapCell::Empty(0); BOARD_SIZE * BOARD_SIZE];
+    for i in 0..BOARD_SIZE {
+        for j in 0..BOARD_SIZE {
+            let r = rng.gen::<f32>();
+            map[i + BOARD_SIZE * j] = if r < 0.2 {
+                MapCell::Wall
+            } else if r < 0.25 {
+                MapCell::Water
+            } else if r < 0.33 {
+                MapCell::Mud
+            } else {
+                MapCell::Empty(rng.gen_range(0..7))
+            };
+        }
+    }
+    let road_x = rng.gen_range(0..BOARD_SIZE);
+    let road_y = rng.gen_range(0..BOARD_SIZE);
+    for i in 0..BOARD_SIZE {
+        map[road_x + BOARD_SIZE * i] = MapCell::Road;
+        map[i + BOARD_SIZE * road_y] = MapCell::Road;
+    }
+    map
+}
+
+fn is_blocked(pos: Pos2, map: &[MapCell], items: &[Pos2]) -> bool {
+    if !map[pos.x as usize + pos.y as usize * BOARD_SIZE].is_passable() {
+        return true;
+    }
+    if items.iter().any(|item| *item == pos) {
+        return true;
+    }
+    false
+}
+
+/// A random cell that is not `blocked`, or `None` if every cell is.
+fn generate_pos(rng: &mut StdRng, blocked: impl Fn(Pos2) -> bool) -> Option<Pos2> {
+    let free: Vec<Pos2> = (0..BOARD_SIZE * BOARD_SIZE)
+        .map(|i| pos2((i % BOARD_SIZE) as f32, (i / BOARD_SIZE) as f32))
+        .filter(|pos| !blocked(*pos))
+        .collect();
+    free.choose(rng).copied()
+}